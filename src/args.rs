@@ -0,0 +1,436 @@
+//! Arguments of directives taken from the AST
+//!
+//! Values are unquoted. Strings the parser keeps as written (location
+//! patterns, regular expressions, `server_name` and `map` keys) are
+//! returned as is, possibly with quotes.
+use nginx_config::ast::{self, Item, Value};
+
+use builder::quote;
+use export::is_quoted;
+
+
+/// Returns text of the value without quotes
+///
+/// `Display` of the value quotes it only if it's empty or contains
+/// whitespace, `;` or braces, and never escapes anything. Values which
+/// start and end with a quote are returned quoted, so they aren't taken
+/// for strings the parser keeps as written.
+pub(crate) fn value(value: &Value) -> String {
+    let text = value.to_string();
+    let quoted = text.len() >= 2 && text.starts_with('"') &&
+        text.ends_with('"') && {
+            let inner = &text[1..text.len()-1];
+            inner.is_empty() || inner.contains(&[
+                ' ', ';', '\r', '\n', '\t', '{', '}',
+            ][..])
+        };
+    let text = if quoted {
+        text[1..text.len()-1].to_string()
+    } else {
+        text
+    };
+    if is_quoted(&text) {
+        if let Ok(quoted) = quote(&text) {
+            return quoted;
+        }
+    }
+    text
+}
+
+fn on_off(flag: bool) -> String {
+    if flag { "on" } else { "off" }.to_string()
+}
+
+/// Returns arguments of the directive, without the name and the block
+///
+/// Arguments of `if` don't include parenthesis. Entries of `map` are
+/// returned by [`map_entries`](fn.map_entries.html).
+pub(crate) fn args(item: &Item) -> Vec<String> {
+    use nginx_config::ast::Item::*;
+    match *item {
+        | Daemon(flag)
+        | MasterProcess(flag)
+        | ProxyPassRequestHeaders(flag)
+        | ProxyPassRequestBody(flag)
+        | ProxyInterceptErrors(flag)
+        | ProxyBuffering(flag)
+        | Gzip(flag)
+        | Etag(flag)
+        | RecursiveErrorPages(flag)
+        | ChunkedTransferEncoding(flag)
+        | RealIpRecursive(flag)
+        => vec![on_off(flag)],
+        WorkerProcesses(ast::WorkerProcesses::Auto) => vec!["auto".into()],
+        WorkerProcesses(ast::WorkerProcesses::Exact(n)) => {
+            vec![n.to_string()]
+        }
+        Http(..) | Server(..) | EmptyGif | Internal => Vec::new(),
+        Location(ref loc) => location_pattern(&loc.pattern),
+        LimitExcept(ref lim) => lim.methods.clone(),
+        Listen(ref lst) => listen(lst),
+        | Root(ref val)
+        | Alias(ref val)
+        | DefaultType(ref val)
+        | ClientMaxBodySize(ref val)
+        | Include(ref val)
+        | RewriteByLuaFile(ref val)
+        | BalancerByLuaFile(ref val)
+        | AccessByLuaFile(ref val)
+        | HeaderFilterByLuaFile(ref val)
+        | ContentByLuaFile(ref val)
+        | BodyFilterByLuaFile(ref val)
+        | LogByLuaFile(ref val)
+        | LuaNeedRequestBody(ref val)
+        | SslCertificateByLuaFile(ref val)
+        | SslSessionFetchByLuaFile(ref val)
+        | SslSessionStoreByLuaFile(ref val)
+        | SslCertificate(ref val)
+        | SslCertificateKey(ref val)
+        | ProxyPass(ref val)
+        | ProxyCache(ref val)
+        | ProxyCacheKey(ref val)
+        | ProxyMethod(ref val)
+        | ProxyReadTimeout(ref val)
+        | ProxyConnectTimeout(ref val)
+        | ProxyHideHeader(ref val)
+        | ProxyPassHeader(ref val)
+        | ProxyNextUpstreamTries(ref val)
+        | ProxyNextUpstreamTimeout(ref val)
+        | ServerTokens(ref val)
+        | RealIpHeader(ref val)
+        => vec![value(val)],
+        ProxySetHeader { ref field, value: ref val } => {
+            vec![value(field), value(val)]
+        }
+        GzipStatic(opt) => vec![opt.to_string()],
+        GzipProxied(ref opts) => {
+            opts.iter().map(|opt| opt.to_string()).collect()
+        }
+        AddHeader(ref hdr) => {
+            let mut args = vec![value(&hdr.field), value(&hdr.value)];
+            if hdr.always {
+                args.push("always".into());
+            }
+            args
+        }
+        ServerName(ref names) => names.iter().map(server_name).collect(),
+        Set { ref variable, value: ref val } => {
+            vec![format!("${}", variable), value(val)]
+        }
+        Map(ref map) => {
+            vec![value(&map.expression), format!("${}", map.variable)]
+        }
+        Rewrite(ref rw) => {
+            use nginx_config::ast::RewriteFlag::*;
+            let mut args = vec![rw.regex.clone(), value(&rw.replacement)];
+            args.extend(rw.flag.as_ref().map(|flag| match *flag {
+                Last => "last",
+                Break => "break",
+                Redirect => "redirect",
+                Permanent => "permanent",
+            }.to_string()));
+            args
+        }
+        ErrorPage(ref ep) => {
+            use nginx_config::ast::ErrorPageResponse::*;
+            let mut args = ep.codes.iter()
+                .map(|code| code.to_string())
+                .collect::<Vec<_>>();
+            match ep.response_code {
+                Target => {}
+                Replace(code) | Redirect(code) => {
+                    args.push(format!("={}", code));
+                }
+                Keep => args.push("=".into()),
+            }
+            args.push(value(&ep.uri));
+            args
+        }
+        Return(ast::Return::Redirect { code, ref url }) => {
+            code.iter().map(|code| code.to_string())
+                .chain(Some(value(url)))
+                .collect()
+        }
+        Return(ast::Return::Text { code, ref text }) => {
+            Some(code.to_string()).into_iter()
+                .chain(text.as_ref().map(value))
+                .collect()
+        }
+        TryFiles(ref tf) => {
+            use nginx_config::ast::TryFilesLastOption::*;
+            let mut args = tf.options.iter().map(value).collect::<Vec<_>>();
+            args.push(match tf.last_option {
+                Uri(ref val) => value(val),
+                NamedLocation(ref name) => format!("@{}", name),
+                Code(code) => format!("={}", code),
+            });
+            args
+        }
+        Expires(ref exp) => {
+            let mut args = Vec::new();
+            if exp.modified {
+                args.push("modified".into());
+            }
+            args.push(value(&exp.value));
+            args
+        }
+        If(ref cond) => if_condition(&cond.condition),
+        Allow(ref source) | Deny(ref source) => {
+            use nginx_config::ast::Source::*;
+            vec![match *source {
+                All => "all".into(),
+                Unix => "unix:".into(),
+                Ip(ip) => ip.to_string(),
+                Network(ip, bits) => format!("{}/{}", ip, bits),
+            }]
+        }
+        ProxyHttpVersion(ast::ProxyHttpVersion::V1_0) => vec!["1.0".into()],
+        ProxyHttpVersion(ast::ProxyHttpVersion::V1_1) => vec!["1.1".into()],
+        ProxyIgnoreHeaders(ref headers) => headers.clone(),
+        ProxyCacheValid(ref valid) => {
+            use nginx_config::ast::ProxyCacheValid::*;
+            match *valid {
+                Normal(ref val) => vec![value(val)],
+                Specific(ref codes, ref val) => {
+                    codes.iter().map(|code| code.to_string())
+                        .chain(Some(value(val)))
+                        .collect()
+                }
+                Any(ref val) => vec!["any".into(), value(val)],
+            }
+        }
+        KeepaliveTimeout(ref timeout, ref header_timeout) => {
+            Some(value(timeout)).into_iter()
+                .chain(header_timeout.as_ref().map(value))
+                .collect()
+        }
+        ProxyNextUpstream(ref flags) => {
+            use nginx_config::ast::ProxyNextUpstreamFlag::*;
+            flags.iter().map(|flag| match *flag {
+                Error => "error",
+                Timeout => "timeout",
+                InvalidHeader => "invalid_header",
+                Http500 => "http_500",
+                Http502 => "http_502",
+                Http503 => "http_503",
+                Http504 => "http_504",
+                Http403 => "http_403",
+                Http404 => "http_404",
+                Http429 => "http_429",
+                NonIdempotent => "non_idempotent",
+                Off => "off",
+            }.to_string()).collect()
+        }
+        AccessLog(ast::AccessLog::Off) => vec!["off".into()],
+        AccessLog(ast::AccessLog::On(ref log)) => access_log(log),
+        SetRealIpFrom(ref source) => {
+            use nginx_config::ast::RealIpFrom::*;
+            vec![match *source {
+                Unix => "unix:".into(),
+                Ip(ip) => ip.to_string(),
+                Network(ip, bits) => format!("{}/{}", ip, bits),
+            }]
+        }
+        ErrorLog { ref file, level } => {
+            use nginx_config::ast::ErrorLevel::*;
+            Some(value(file)).into_iter()
+                .chain(level.map(|level| match level {
+                    Debug => "debug",
+                    Info => "info",
+                    Notice => "notice",
+                    Warn => "warn",
+                    Error => "error",
+                    Crit => "crit",
+                    Alert => "alert",
+                    Emerg => "emerg",
+                }.to_string()))
+                .collect()
+        }
+        Index(ref items) => items.iter().map(value).collect(),
+    }
+}
+
+/// Returns entries of the `map` block, each one is a key and arguments
+pub(crate) fn map_entries(map: &ast::Map) -> Vec<Vec<String>> {
+    use nginx_config::ast::MapPattern::*;
+    let mut entries = Vec::new();
+    if map.volatile {
+        entries.push(vec!["volatile".into()]);
+    }
+    if map.hostnames {
+        entries.push(vec!["hostnames".into()]);
+    }
+    if let Some(ref default) = map.default {
+        entries.push(vec!["default".into(), value(default)]);
+    }
+    for include in &map.includes {
+        entries.push(vec!["include".into(), include.clone()]);
+    }
+    for (pattern, val) in &map.patterns {
+        let key = match *pattern {
+            Exact(ref key) if matches!(&key[..],
+                "volatile" | "hostnames" | "default" | "include")
+            => format!("\\{}", key),
+            Exact(ref key) => key.clone(),
+            Suffix(ref key) => format!(".{}", key),
+            StarSuffix(ref key) => format!("*.{}", key),
+            StarPrefix(ref key) => format!("{}.*", key),
+            Regex(ref key) => format!("~{}", key),
+        };
+        entries.push(vec![key, value(val)]);
+    }
+    entries
+}
+
+fn location_pattern(pattern: &ast::LocationPattern) -> Vec<String> {
+    use nginx_config::ast::LocationPattern::*;
+    match *pattern {
+        Prefix(ref p) => vec![p.clone()],
+        Exact(ref p) => vec!["=".into(), p.clone()],
+        FinalPrefix(ref p) => vec!["^~".into(), p.clone()],
+        Regex(ref p) => vec!["~".into(), p.clone()],
+        RegexInsensitive(ref p) => vec!["~*".into(), p.clone()],
+        Named(ref name) => vec![format!("@{}", name)],
+    }
+}
+
+fn server_name(name: &ast::ServerName) -> String {
+    use nginx_config::ast::ServerName::*;
+    match *name {
+        Exact(ref name) => name.clone(),
+        Suffix(ref name) => format!(".{}", name),
+        StarSuffix(ref name) => format!("*.{}", name),
+        StarPrefix(ref name) => format!("{}.*", name),
+        Regex(ref name) => format!("~{}", name),
+    }
+}
+
+fn if_condition(cond: &ast::IfCondition) -> Vec<String> {
+    use nginx_config::ast::IfCondition::*;
+    let (op, val, arg) = match *cond {
+        NonEmpty(ref val) => return vec![value(val)],
+        Eq(ref val, ref arg) => ("=", val, arg),
+        Neq(ref val, ref arg) => ("!=", val, arg),
+        RegEq(ref val, ref arg, true) => ("~", val, arg),
+        RegEq(ref val, ref arg, false) => ("~*", val, arg),
+        RegNeq(ref val, ref arg, true) => ("!~", val, arg),
+        RegNeq(ref val, ref arg, false) => ("!~*", val, arg),
+        Exists(ref val) => return vec!["-e".into(), value(val)],
+        NotExists(ref val) => return vec!["!-e".into(), value(val)],
+        FileExists(ref val) => return vec!["-f".into(), value(val)],
+        FileNotExists(ref val) => return vec!["!-f".into(), value(val)],
+        DirExists(ref val) => return vec!["-d".into(), value(val)],
+        DirNotExists(ref val) => return vec!["!-d".into(), value(val)],
+        Executable(ref val) => return vec!["-x".into(), value(val)],
+        NotExecutable(ref val) => return vec!["!-x".into(), value(val)],
+    };
+    vec![value(val), op.into(), arg.clone()]
+}
+
+fn listen(lst: &ast::Listen) -> Vec<String> {
+    let mut args = vec![match lst.address {
+        ast::Address::Ip(addr) => addr.to_string(),
+        ast::Address::StarPort(port) => format!("*:{}", port),
+        ast::Address::Port(port) => port.to_string(),
+        ast::Address::Unix(ref path) => format!("unix:{}", path.display()),
+    }];
+    let mut flag = |set: bool, name: &str| if set {
+        args.push(name.to_string());
+    };
+    flag(lst.default_server, "default_server");
+    flag(lst.ssl, "ssl");
+    flag(lst.ext == Some(ast::HttpExt::Http2), "http2");
+    flag(lst.ext == Some(ast::HttpExt::Spdy), "spdy");
+    flag(lst.proxy_protocol, "proxy_protocol");
+    args.extend(lst.setfib.map(|x| format!("setfib={}", x)));
+    args.extend(lst.fastopen.map(|x| format!("fastopen={}", x)));
+    args.extend(lst.backlog.map(|x| format!("backlog={}", x)));
+    args.extend(lst.rcvbuf.map(|x| format!("rcvbuf={}", x)));
+    args.extend(lst.sndbuf.map(|x| format!("sndbuf={}", x)));
+    if lst.deferred {
+        args.push("deferred".into());
+    }
+    if lst.bind {
+        args.push("bind".into());
+    }
+    args.extend(lst.ipv6only.map(|x| format!("ipv6only={}", on_off(x))));
+    if lst.reuseport {
+        args.push("reuseport".into());
+    }
+    args
+}
+
+fn access_log(log: &ast::AccessLogOptions) -> Vec<String> {
+    let mut args = vec![value(&log.path)];
+    args.extend(log.format.clone());
+    args.extend(log.buffer.as_ref().map(|x| format!("buffer={}", x)));
+    args.extend(log.gzip.map(|level| match level {
+        Some(level) => format!("gzip={}", level),
+        None => "gzip".into(),
+    }));
+    args.extend(log.flush.as_ref().map(|x| format!("flush={}", x)));
+    args.extend(log.condition.as_ref().map(|x| format!("if={}", value(x))));
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use nginx_config::parse_directives;
+    use super::args;
+
+    fn parse(text: &str) -> Vec<String> {
+        let dirs = parse_directives(text).unwrap();
+        args(&dirs[0].item)
+    }
+
+    #[test]
+    fn unquote() {
+        assert_eq!(parse("root \"/var/www\";"), vec!["/var/www"]);
+        assert_eq!(parse("root '/a b';"), vec!["/a b"]);
+        assert_eq!(parse("add_header X-A \"\" always;"),
+                   vec!["X-A", "", "always"]);
+        assert_eq!(parse("add_header X-A 'say \"hi\"';"),
+                   vec!["X-A", "say \"hi\""]);
+        assert_eq!(parse("set $a \"'b'\";"), vec!["$a", "\"'b'\""]);
+    }
+
+    #[test]
+    fn unquote_specials() {
+        assert_eq!(parse("set $a \"b;c\";"), vec!["$a", "b;c"]);
+        assert_eq!(parse("set $a '{b}';"), vec!["$a", "{b}"]);
+        assert_eq!(parse("set $a \"b\tc\";"), vec!["$a", "b\tc"]);
+        assert_eq!(parse("set $a \"$b c\";"), vec!["$a", "$b c"]);
+        assert_eq!(parse("set $a \"#b\";"), vec!["$a", "#b"]);
+        assert_eq!(parse("set $a \"it's\";"), vec!["$a", "it's"]);
+        assert_eq!(parse("set $a b\\\"c;"), vec!["$a", "b\\\"c"]);
+        assert_eq!(parse("set $a '\"b\"';"), vec!["$a", "'\"b\"'"]);
+    }
+
+    #[test]
+    fn if_condition() {
+        assert_eq!(parse("if ($a = 'x') { return 403; }"),
+                   vec!["$a", "=", "'x'"]);
+        assert_eq!(parse("if (!-f $uri) { return 404; }"),
+                   vec!["!-f", "$uri"]);
+    }
+
+    #[test]
+    fn listen() {
+        assert_eq!(parse("listen 127.0.0.1:80 default_server ssl;"),
+                   vec!["127.0.0.1:80", "default_server", "ssl"]);
+        assert_eq!(parse("listen [::]:443 http2 ipv6only=on;"),
+                   vec!["[::]:443", "http2", "ipv6only=on"]);
+    }
+
+    #[test]
+    fn misc() {
+        assert_eq!(parse("location ~* \\.php$ { }"), vec!["~*", "\\.php$"]);
+        assert_eq!(parse("try_files $uri /index.html =404;"),
+                   vec!["$uri", "/index.html", "=404"]);
+        assert_eq!(parse("error_page 500 502 =200 /50x.html;"),
+                   vec!["500", "502", "=200", "/50x.html"]);
+        assert_eq!(parse("access_log /log main buffer=32k if=$log;"),
+                   vec!["/log", "main", "buffer=32k", "if=$log"]);
+        assert_eq!(parse("set $x \"a b\";"), vec!["$x", "a b"]);
+    }
+}
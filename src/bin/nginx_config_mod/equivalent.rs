@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use failure::{Error, err_msg};
+use nginx_config_mod::{Config, EntryPoint, CanonicalError};
+use nginx_config_mod::includes::Expander;
+
+use report;
+
+#[derive(StructOpt)]
+pub struct Equivalent {
+    #[structopt(parse(from_os_str), help="First config file")]
+    first: PathBuf,
+
+    #[structopt(parse(from_os_str), help="Second config file")]
+    second: PathBuf,
+
+    #[structopt(flatten)]
+    options: CanonicalOptions,
+}
+
+#[derive(StructOpt)]
+pub struct Canonicalize {
+    #[structopt(parse(from_os_str),
+                help="Config file, `-` to read standard input")]
+    file: PathBuf,
+
+    #[structopt(long="hash", help="\
+        Print FNV-1a hash of the canonical form instead of the config. \
+        The hash may be stored to detect manual edits, it changes only \
+        when a new version of the tool changes the canonical form. \
+        ")]
+    hash: bool,
+
+    #[structopt(flatten)]
+    options: CanonicalOptions,
+}
+
+#[derive(StructOpt)]
+pub struct CanonicalOptions {
+    #[structopt(long="entry-point", name="CONTEXT", default_value="main",
+                help="\
+        Context the files are included in: \
+        `main`, `http`, `server` or `location`. \
+        ")]
+    entry_point: EntryPoint,
+
+    #[structopt(long="include-root", name="DIR", parse(from_os_str),
+                help="\
+        Expand absolute includes too, treating the directory as \
+        a filesystem root. Relative includes are always expanded, \
+        resolved against the directory of the file. \
+        ")]
+    include_root: Option<PathBuf>,
+}
+
+fn read_canonical(path: &Path, options: &CanonicalOptions)
+    -> Result<Config, Error>
+{
+    let (mut cfg, reporter) = report::read_config(options.entry_point, path)?;
+    let mut expander = Expander::new(path.parent()
+        .expect("file path always has parent"));
+    if let Some(ref root) = options.include_root {
+        expander.include_root(root);
+    }
+    match cfg.canonicalize(&expander) {
+        Ok(()) => Ok(cfg),
+        Err(CanonicalError::Include(e)) => {
+            reporter.report_include(&e);
+            Err(err_msg("can't expand includes"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn run_equivalent(eq: Equivalent) -> Result<(), Error> {
+    let first = read_canonical(&eq.first, &eq.options)?;
+    let second = read_canonical(&eq.second, &eq.options)?;
+    if first.equivalent(&second) {
+        return Ok(());
+    }
+    let first = first.canonical_text();
+    let second = second.canonical_text();
+    let mut lines = first.lines().zip(second.lines())
+        .enumerate()
+        .skip_while(|&(_, (a, b))| a == b);
+    match lines.next() {
+        Some((num, (a, b))) => {
+            println!("line {} of the canonical form:", num + 1);
+            println!("- {}", a);
+            println!("+ {}", b);
+        }
+        None => {
+            println!("line {} of the canonical form:",
+                     first.lines().count().min(second.lines().count()) + 1);
+            println!("one of the configs is longer");
+        }
+    }
+    Err(err_msg("configs are not equivalent"))
+}
+
+pub fn run_canonicalize(canon: Canonicalize) -> Result<(), Error> {
+    let cfg = read_canonical(&canon.file, &canon.options)?;
+    if canon.hash {
+        println!("{:016x}", cfg.content_hash());
+    } else {
+        print!("{}", cfg.canonical_text());
+    }
+    Ok(())
+}
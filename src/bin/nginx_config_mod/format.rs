@@ -0,0 +1,340 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, chown};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use failure::{Error, err_msg};
+use nginx_config_mod::{Config, EntryPoint, has_comments};
+use nginx_config_mod::diagnostics::Diagnostic;
+
+use report::{self, Reporter};
+
+#[derive(StructOpt)]
+pub struct Format {
+    #[structopt(parse(from_os_str), raw(required="true"), help="\
+        Config files or directories to format, directories are searched \
+        for `*.conf` files recursively. `-` reads standard input. \
+        ")]
+    files: Vec<PathBuf>,
+
+    #[structopt(long="entry-point", name="CONTEXT", default_value="main",
+                help="\
+        Context the files are included in: \
+        `main`, `http`, `server` or `location`. \
+        ")]
+    entry_point: EntryPoint,
+
+    #[structopt(long="check", help="\
+        Don't print anything, list files which are not formatted and \
+        exit with non-zero code if there are any. Files with comments \
+        are skipped with a warning, as formatting removes comments. \
+        ")]
+    check: bool,
+
+    #[structopt(long="in-place", help="\
+        Write formatted config back to the files which are not \
+        formatted. Files with comments are skipped with a warning, \
+        as formatting would remove the comments. \
+        ")]
+    in_place: bool,
+
+    #[structopt(long="indent", name="SPACES", default_value="4",
+                help="Number of spaces per indentation level")]
+    indent: usize,
+
+    #[structopt(long="from-dump", help="\
+        The file is output of `nginx -T`: print the first file in it \
+        with includes expanded from the dump.")]
+    from_dump: bool,
+}
+
+pub fn run(fmt: Format) -> Result<(), Error> {
+    if fmt.check && fmt.in_place {
+        return Err(err_msg("`--check` and `--in-place` are exclusive"));
+    }
+    if fmt.from_dump {
+        return format_dump(&fmt);
+    }
+    let mut files = Vec::new();
+    for path in &fmt.files {
+        if path.is_dir() {
+            find_configs(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    if fmt.in_place && files.iter().any(|p| report::is_stdin(p)) {
+        return Err(err_msg("can't write standard input in place"));
+    }
+    let mut failed = 0;
+    let mut unformatted = 0;
+    for path in &files {
+        let (text, formatted) = match format_file(&fmt, path) {
+            Ok(pair) => pair,
+            Err(e) => {
+                error!("{}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let reporter = Reporter::with_source(&name(path), None);
+        if has_comments(&text) && (fmt.check || fmt.in_place) {
+            reporter.diagnostic(&Diagnostic::new(
+                "skipped: file has comments which formatting would remove")
+                .warning());
+            continue;
+        }
+        if fmt.check {
+            if text != formatted {
+                println!("{}", path.display());
+                unformatted += 1;
+            }
+        } else if fmt.in_place {
+            if text != formatted {
+                write_atomic(path, &formatted)?;
+                info!("formatted {:?}", path);
+            }
+        } else {
+            if has_comments(&text) {
+                reporter.diagnostic(&Diagnostic::new(
+                    "file has comments which are removed").warning());
+            }
+            print!("{}", formatted);
+        }
+    }
+    if failed > 0 {
+        return Err(format_err!("{} file(s) could not be formatted", failed));
+    }
+    if unformatted > 0 {
+        return Err(format_err!("{} file(s) are not formatted",
+                               unformatted));
+    }
+    Ok(())
+}
+
+fn format_dump(fmt: &Format) -> Result<(), Error> {
+    if fmt.check || fmt.in_place || fmt.files.len() != 1 {
+        return Err(err_msg("`--from-dump` needs a single file \
+                            and can only print the result"));
+    }
+    let dump = report::read_dump(&fmt.files[0])?;
+    let (mut cfg, reporter, _) = report::read_dump_config(
+        fmt.entry_point, &dump, false)?;
+    if let Err(e) = dump.expander().expand(&mut cfg) {
+        reporter.report_include(&e);
+        return Err(err_msg("can't expand includes"));
+    }
+    print!("{}", cfg.to_string_indented(fmt.indent));
+    Ok(())
+}
+
+/// Returns original and formatted text of the file
+fn format_file(fmt: &Format, path: &Path) -> Result<(String, String), Error> {
+    let text = report::read_input(path)?;
+    let name = name(path);
+    let cfg = match Config::parse_str(fmt.entry_point, &text, &name) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            Reporter::with_source(&name, Some(text)).report(&e);
+            return Err(format_err!("can't read {}", name));
+        }
+    };
+    let formatted = cfg.to_string_indented(fmt.indent);
+    // never replace a valid file with one that means something else
+    match Config::parse_str(fmt.entry_point, &formatted, &name) {
+        Ok(ref copy) if copy.equivalent(&cfg) => {}
+        Ok(_) => {
+            return Err(format_err!("formatted {} differs from the original",
+                                   name));
+        }
+        Err(_) => {
+            return Err(format_err!("formatted {} can't be parsed back",
+                                   name));
+        }
+    }
+    Ok((text, formatted))
+}
+
+/// Name of the file in diagnostics
+fn name(path: &Path) -> String {
+    if report::is_stdin(path) {
+        report::STDIN_NAME.to_string()
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Collects `*.conf` files in the directory, sorted
+fn find_configs(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|e| format_err!("can't read directory {:?}: {}", dir, e))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format_err!("can't read directory {:?}: {}", dir, e))?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_configs(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "conf") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the file, so it's never left partially written
+///
+/// Symlinks are resolved, so the file they point to is replaced. Mode of
+/// the file is kept, and on unix its owner too.
+fn write_atomic(path: &Path, data: &str) -> Result<(), Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = fs::canonicalize(path)
+        .map_err(|e| format_err!("can't resolve {:?}: {}", path, e))?;
+    let meta = fs::metadata(&path)
+        .map_err(|e| format_err!("can't read {:?}: {}", path, e))?;
+    let name = path.file_name()
+        .ok_or_else(|| format_err!("invalid file name {:?}", path))?;
+    let tmp = path.with_file_name(format!(".{}.{}.{}.tmp",
+        name.to_string_lossy(), process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let mut file = create_private(&tmp)
+        .map_err(|e| format_err!("can't create {:?}: {}", tmp, e))?;
+    let result = file.write_all(data.as_bytes())
+        .and_then(|()| keep_owner(&file, &tmp, &meta))
+        .and_then(|()| file.set_permissions(meta.permissions()))
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(&tmp, &path));
+    result.map_err(|e| {
+        fs::remove_file(&tmp).ok();
+        format_err!("error writing {:?}: {}", path, e)
+    })
+}
+
+/// Creates a new file only the owner can read
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+/// Gives the new file owner of the replaced one
+#[cfg(unix)]
+fn keep_owner(file: &File, path: &Path, meta: &Metadata) -> io::Result<()> {
+    let created = file.metadata()?;
+    if (meta.uid(), meta.gid()) != (created.uid(), created.gid()) {
+        chown(path, Some(meta.uid()), Some(meta.gid()))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn keep_owner(_file: &File, _path: &Path, _meta: &Metadata)
+    -> io::Result<()>
+{
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use structopt::StructOpt;
+
+    use super::{Format, run, write_atomic};
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(format!("nginx-config-mod-format-{}-{}",
+                                          name, process::id()));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn format(args: &[&str], path: &Path) -> Result<(), ()> {
+        let path = path.to_str().unwrap();
+        let fmt = Format::from_iter(
+            ["format", "--entry-point=http"].iter().chain(args)
+            .chain(&[path]));
+        run(fmt).map_err(|_| ())
+    }
+
+    #[test]
+    fn check() {
+        let dir = dir("check");
+        let path = dir.join("a.conf");
+        write(&path, "server {\n    listen 80;\n}\n").unwrap();
+        assert_eq!(format(&["--check"], &path), Ok(()));
+        write(&path, "server { listen 80; }").unwrap();
+        assert_eq!(format(&["--check"], &path), Err(()));
+        // skipped, as formatting would remove the comment
+        write(&path, "server { listen 80; } # x").unwrap();
+        assert_eq!(format(&["--check"], &path), Ok(()));
+        assert_eq!(format(&["--check", "--in-place"], &path), Err(()));
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_place() {
+        let dir = dir("in-place");
+        let path = dir.join("a.conf");
+        write(&path, "server { listen 80; location / { root /a; } }")
+            .unwrap();
+        assert_eq!(format(&["--in-place", "--indent=2"], &path), Ok(()));
+        assert_eq!(read_to_string(&path).unwrap(),
+                   "server {\n  listen 80;\n\n  \
+                    location / {\n    root /a;\n  }\n}\n");
+        assert_eq!(format(&["--check", "--indent=2"], &path), Ok(()));
+        assert_eq!(format(&["--check"], &path), Err(()));
+        let text = "server { listen 80; } # x";
+        write(&path, text).unwrap();
+        assert_eq!(format(&["--in-place"], &path), Ok(()));
+        assert_eq!(read_to_string(&path).unwrap(), text);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic() {
+        let dir = dir("atomic");
+        let path = dir.join("a.conf");
+        write(&path, "old").unwrap();
+        write_atomic(&path, "new").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "new");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_unix() {
+        use std::fs::{metadata, set_permissions, symlink_metadata};
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let dir = dir("atomic-unix");
+        let path = dir.join("a.conf");
+        let link = dir.join("b.conf");
+        write(&path, "old").unwrap();
+        set_permissions(&path, PermissionsExt::from_mode(0o640)).unwrap();
+        symlink(&path, &link).unwrap();
+        write_atomic(&link, "new").unwrap();
+        assert!(symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(read_to_string(&path).unwrap(), "new");
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777,
+                   0o640);
+        remove_dir_all(&dir).unwrap();
+    }
+}
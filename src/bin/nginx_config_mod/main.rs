@@ -7,16 +7,24 @@ extern crate nginx_config_mod;
 #[macro_use] extern crate matches;
 #[macro_use] extern crate structopt;
 
+mod equivalent;
+mod format;
 mod modify;
+mod policy;
+mod report;
 mod validate;
 
 use std::path::PathBuf;
 use std::process::exit;
 
-use failure::Error;
+use failure::{Error, err_msg};
 use structopt::StructOpt;
-use nginx_config_mod::{Config, EntryPoint};
+use nginx_config_mod::EntryPoint;
+use nginx_config_mod::export;
+use nginx_config_mod::includes::Expander;
 
+use equivalent::{Canonicalize, Equivalent};
+use format::Format;
 use modify::Modify;
 use validate::Validate;
 
@@ -30,11 +38,66 @@ enum Options {
 
     #[structopt(name = "format",
                 about="Format (prettify) nginx configuration")]
-    Format {
-        #[structopt(parse(from_os_str))]
+    Format(Format),
+
+    #[structopt(name="unpack",
+                about="Recreate config files from `nginx -T` output")]
+    Unpack {
+        #[structopt(parse(from_os_str),
+                    help="Output of `nginx -T`, `-` to read standard input")]
+        dump: PathBuf,
+
+        #[structopt(parse(from_os_str), help="\
+            Directory to write files to, absolute paths in the dump are \
+            treated as relative to it.")]
+        dir: PathBuf,
+    },
+
+    #[structopt(name="export",
+                about="Print config as a JSON or YAML tree")]
+    Export {
+        #[structopt(parse(from_os_str),
+                    help="Config file, `-` to read standard input")]
+        file: PathBuf,
+
+        #[structopt(long="entry-point", name="CONTEXT",
+                    default_value="main", help="\
+            Context the file is included in: \
+            `main`, `http`, `server` or `location`.")]
+        entry_point: EntryPoint,
+
+        #[structopt(long="format", name="FORMAT", default_value="json",
+                    help="Output format: `json` or `yaml`")]
+        format: export::Format,
+
+        #[structopt(long="expand-local-includes", help="\
+            Read relative includes (resolved against the directory of the \
+            file) and put their directives in place, with the file they \
+            come from.")]
+        expand_local_includes: bool,
+    },
+
+    #[structopt(name="import",
+                about="Print nginx config from a JSON or YAML tree")]
+    Import {
+        #[structopt(parse(from_os_str),
+                    help="Tree printed by `export`, \
+                          `-` to read standard input")]
         file: PathBuf,
+
+        #[structopt(long="format", name="FORMAT", default_value="json",
+                    help="Input format: `json` or `yaml`")]
+        format: export::Format,
     },
 
+    #[structopt(name="equivalent",
+                about="Check that configs have the same canonical form")]
+    Equivalent(Equivalent),
+
+    #[structopt(name="canonicalize",
+                about="Print canonical form of the config or its hash")]
+    Canonicalize(Canonicalize),
+
     #[structopt(name="modify",
                 about="Apply various modifications to config")]
     Modify(Modify),
@@ -46,9 +109,42 @@ fn run(opt: Options) -> Result<(), Error> {
         Validate(validate) => {
             validate::run(validate)?
         }
-        Format { file } => {
-            let cfg = Config::partial_file(EntryPoint::Main, &file)?;
-            print!("{}", cfg);
+        Format(format) => {
+            format::run(format)?
+        }
+        Export { file, entry_point, format, expand_local_includes } => {
+            let (mut cfg, reporter) = report::read_config(entry_point,
+                                                          &file)?;
+            if expand_local_includes {
+                let expander = Expander::new(file.parent()
+                    .expect("file path always has parent"));
+                if let Err(e) = expander.expand(&mut cfg) {
+                    reporter.report_include(&e);
+                    return Err(err_msg("can't expand includes"));
+                }
+            }
+            print!("{}", export::export(&cfg, format));
+        }
+        Import { file, format } => {
+            let text = report::read_input(&file)?;
+            let name = if report::is_stdin(&file) {
+                report::STDIN_NAME.to_string()
+            } else {
+                file.display().to_string()
+            };
+            let cfg = export::import(&text, format, &name)?;
+            print!("{}", cfg.to_string_indented(4));
+        }
+        Unpack { dump, dir } => {
+            for path in report::read_dump(&dump)?.unpack(&dir)? {
+                info!("written {:?}", path);
+            }
+        }
+        Equivalent(eq) => {
+            equivalent::run_equivalent(eq)?
+        }
+        Canonicalize(canon) => {
+            equivalent::run_canonicalize(canon)?
         }
         Modify(modify) => {
             modify::run(modify)?
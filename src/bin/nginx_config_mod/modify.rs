@@ -1,25 +1,40 @@
-use std::collections::HashMap;
-use std::mem;
-use std::path::{PathBuf, Path};
-use std::fs::read_to_string;
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, Ipv6Addr};
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use failure::{Error, ResultExt, err_msg};
-use nginx_config;
-use nginx_config::ast::{self, Listen, Value, Directive, Item};
+use nginx_config::ast::{self, Listen, Address, Value, Directive, Item};
 use nginx_config::{parse_directives, Pos};
 use nginx_config::visitors::{replace_vars, visit_mutable};
 use regex::Regex;
 
 use nginx_config_mod::{Config, EntryPoint};
+use nginx_config_mod::includes::{Expander, AllowList};
 use nginx_config_mod::checks;
+use nginx_config_mod::diagnostics::{Diagnostic, ToDiagnostic};
+use nginx_config_mod::checks::listen::{wildcard_port, has_socket_options};
+
+use policy::{self, PolicyOptions};
+use report::{self, Reporter};
 
 #[derive(StructOpt)]
 pub struct Modify {
 
-    #[structopt(parse(from_os_str))]
+    #[structopt(parse(from_os_str),
+                help="Config file, `-` to read standard input")]
     file: PathBuf,
 
+    #[structopt(long="entry-point", name="CONTEXT", default_value="main",
+                help="Context the file is included in: \
+                      `main`, `http`, `server` or `location`")]
+    entry_point: EntryPoint,
+
+    #[structopt(long="from-dump",
+                help="The file is output of `nginx -T`: modify the first \
+                      file in it with includes expanded from the dump")]
+    from_dump: bool,
+
     #[structopt(short="s", long="subst-variable", name="var=value",
                 help="replace variable in the config to specified value")]
     set_var: Vec<String>,
@@ -29,6 +44,24 @@ pub struct Modify {
                       to a dest.domain (keeping prefix if needed)")]
     server_name_mapping: Vec<String>,
 
+    #[structopt(long="rename-domain", name="old.domain=new.domain",
+                help="replace old.domain and all its subdomains to \
+                      new.domain (keeping prefix) everywhere it's used \
+                      as a host: `server_name`, redirect urls in `return`, \
+                      `rewrite` and `error_page`, `Location` and \
+                      `Access-Control-Allow-Origin` headers, `map` keys \
+                      and values. Other occurrences of the domain are \
+                      listed as errors but kept intact.")]
+    rename_domain: Vec<String>,
+
+    #[structopt(long="strict-server-names", help="\
+        Fail if some server name or map pattern mentioning the domain \
+        can't be substituted by `--subst-server-name` or `--rename-domain`. \
+        Star-prefix names (`www.example.*`) are substituted only when \
+        both domains have at least two labels, regex names only when \
+        they contain the domain literally.")]
+    strict_server_names: bool,
+
     #[structopt(long="subst-proxy-pass-host", name="orig.host=dest.host",
                 help="replace orig.host and all names starting with it \
                       to a dest.host (keeping prefix and port). \
@@ -50,9 +83,10 @@ pub struct Modify {
     if_regexes: Vec<String>,
 
     #[structopt(long="regex-subst-rewrite-host", name="HOST_REGEX=SUBST",
-                help="replace REGEX in host for rewrite directives. \
+                help="replace REGEX in host for `rewrite`, `return`, \
+                      `error_page` directives and `Location` header. \
                       This work only for `http://host` or `https://host`,
-                      or `$scheme://host` rewrites not local ones.
+                      or `$scheme://host` urls not local ones.
                       Destination substitution may contain capture groups \
                       '$0', '$1'... ")]
     rewrite_host_regexes: Vec<String>,
@@ -76,18 +110,39 @@ pub struct Modify {
 
     #[structopt(long="expand-local-includes", help="\
         Expand non-absolute includes to their contents. \
-        Include path is treated relative to the configuration file path. \
-        Including works before all other directives so included contents is \
-        processed normally.")]
+        Include path is treated relative to the configuration file path, \
+        both in the file itself and in included files (the way nginx \
+        resolves them relative to the prefix). Includes in included \
+        files are expanded too, glob patterns are expanded in sorted \
+        order. Including works before all other directives so included \
+        contents is processed normally.")]
     expand_local_includes: bool,
 
+    #[structopt(long="include-root", help="\
+        Expand absolute includes too, looking them up in this directory \
+        as if it was a filesystem root. Implies `--expand-local-includes`.",
+        parse(from_os_str))]
+    include_root: Option<PathBuf>,
+
     #[structopt(long="allow-includes", help="\
         Add a path prefix to a list of allowed include directive prefixes. \
-        Note: these includes aren't read and checked so may possibly \
-        contain disallowed things if not checked on its own.",
+        Paths are normalized before comparison, so `..` can't escape the \
+        prefix. Prefixes may contain glob patterns, glob in include is \
+        allowed only if the prefix has the same pattern or `*` there. \
+        When includes are expanded, includes in included files are \
+        checked too. Note: includes which are not expanded aren't read \
+        and checked so may possibly contain disallowed things if not \
+        checked on its own.",
         parse(from_os_str))]
     allow_includes: Vec<PathBuf>,
 
+    #[structopt(long="canonicalize-includes", help="\
+        Resolve symlinks in included files and in allowed prefixes before \
+        checking `--allow-includes`, so that a symlink in the allowed \
+        directory can't point outside of it. With `--include-root`, \
+        symlinks under the root are resolved inside of it.")]
+    canonicalize_includes: bool,
+
     #[structopt(long="listen", name="LISTEN",
                 help="replace all listen directives to this value. \
                 If used multiple times. This number of listen directives will \
@@ -95,6 +150,35 @@ pub struct Modify {
                 parse(try_from_str="parse_listen"))]
     listen: Vec<Listen>,
 
+    #[structopt(long="map-listen", name="FROM=TO",
+                help="change address of matching listen directives keeping \
+                all other listen options (`ssl`, `default_server`...). \
+                Port-only FROM (`80=8080`) matches any address with that \
+                port, and if TO is port-only too, only port is changed. \
+                If TO is a full address, only addresses of the same family \
+                (IPv4 or IPv6, IPv4 for unix sockets) match, so \
+                `80=127.0.0.1:8080` keeps `[::]:80` as is. \
+                Otherwise addresses must match exactly, where `80`, `*:80` \
+                and `0.0.0.0:80` are the same address. \
+                First matching mapping is applied.",
+                parse(try_from_str="parse_listen_mapping"))]
+    map_listen: Vec<(Address, Address)>,
+
+    #[structopt(long="mirror-ipv6", help="\
+        Add `[::]:PORT` listen directive next to every IPv4 wildcard \
+        listen which has no IPv6 counterpart in the same server, \
+        with the same options. `default_server` and socket options \
+        (`reuseport`, `backlog`...) are omitted if the same IPv6 port \
+        already has them elsewhere. Applied after `--listen` and \
+        `--map-listen`.")]
+    mirror_ipv6: bool,
+
+    #[structopt(long="migrate", help="\
+        Rewrite deprecated directives into their modern equivalents \
+        (currently `spdy` listen parameter into `http2`). Every change \
+        is reported to stderr.")]
+    migrate: bool,
+
     #[structopt(long="replace-by-name", name="DIR=VALUE", help="\
         Replace any occurrence of directve named DIR with another directive, \
         specified in VALUE. For example ``expires=add_header Expires never``. \
@@ -103,6 +187,9 @@ pub struct Modify {
         took place. Only one rule is applied to each directive.",
         parse(try_from_str="parse_replacement"))]
     replace_by_name: Vec<(String, Item)>,
+
+    #[structopt(flatten)]
+    policy: PolicyOptions,
 }
 
 fn parse_listen(s: &str) -> Result<Listen, Error> {
@@ -117,6 +204,25 @@ fn parse_listen(s: &str) -> Result<Listen, Error> {
     }
 }
 
+fn parse_address(s: &str) -> Result<Address, Error> {
+    let lst = parse_listen(s)?;
+    if lst != Listen::new(lst.address.clone()) {
+        bail!("only address is expected in {:?}", s);
+    }
+    Ok(lst.address)
+}
+
+fn parse_listen_mapping(s: &str) -> Result<(Address, Address), Error> {
+    let mut pair = s.splitn(2, '=');
+    let from = pair.next().expect("first item always exists");
+    match pair.next() {
+        Some(to) => Ok((parse_address(from)?, parse_address(to)?)),
+        None => bail!("listen mapping {:?} doesn't include destination \
+                       (format is `80=8080` or `0.0.0.0:443=127.0.0.1:8443`)",
+                       s),
+    }
+}
+
 fn parse_replacement(s: &str) -> Result<(String, Item), Error> {
     let mut pair = s.splitn(2, "=");
     let name = pair.next().unwrap();
@@ -178,10 +284,13 @@ fn proxy_subst<'x>(name: &'x str, anchor: &str) -> Option<(&'x str, &'x str)> {
     } else if name.starts_with("https://") {
         cur = &cur["https://".len()..];
         prefix += "https://".len();
+    } else if name.starts_with("$scheme://") {
+        cur = &cur["$scheme://".len()..];
+        prefix += "$scheme://".len();
     } else {
         return None;
     }
-    if let Some(suf) = cur.find('/') {
+    if let Some(suf) = cur.find(&['/', '$'][..]) {
         suffix += cur.len() - suf;
         cur = &cur[..suf];
     }
@@ -205,45 +314,353 @@ fn proxy_subst<'x>(name: &'x str, anchor: &str) -> Option<(&'x str, &'x str)> {
 }
 
 
-fn server_names(cfg: &mut Config, names: &Vec<String>)
-    -> Result<(), Error>
+enum Rename {
+    Done,
+    Unrelated,
+    Unsafe,
+}
+
+fn rename_host(name: &mut String, domains: &[(&str, &str)]) -> Rename {
+    for &(orig, new) in domains {
+        if let Some(prefix) = relative(name, orig) {
+            *name = format!("{}{}", prefix, new);
+            return Rename::Done;
+        }
+    }
+    Rename::Unrelated
+}
+
+/// Renames `www.example.*` into `www.test.*` for `example.com=test.local`
+fn rename_star_prefix(name: &mut String, domains: &[(&str, &str)]) -> Rename {
+    for &(orig, new) in domains {
+        let stem = match orig.rfind('.') {
+            Some(end) => &orig[..end],
+            None => continue,
+        };
+        if let Some(prefix) = relative(name, stem) {
+            return match new.rfind('.') {
+                Some(end) => {
+                    *name = format!("{}{}", prefix, &new[..end]);
+                    Rename::Done
+                }
+                None => Rename::Unsafe,
+            };
+        }
+    }
+    Rename::Unrelated
+}
+
+/// Replaces literal domain in a regex, both `example\.com` and sloppy
+/// `example.com` forms are replaced by the escaped destination domain
+fn rename_regex(name: &mut String, domains: &[(&str, &str)]) -> Rename {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '-';
+    let mut result = Rename::Unrelated;
+    for &(orig, new) in domains {
+        let labels = orig.split('.').map(regex::escape).collect::<Vec<_>>();
+        let literal = Regex::new(&labels.join(r"\\?\."))
+            .expect("escaped domain is a valid regex");
+        let mut buf = String::with_capacity(name.len());
+        let mut last = 0;
+        for m in literal.find_iter(name) {
+            if name[..m.start()].chars().next_back().is_some_and(is_name_char)
+               || name[m.end()..].chars().next().is_some_and(is_name_char)
+            {
+                continue;
+            }
+            buf.push_str(&name[last..m.start()]);
+            buf.push_str(&regex::escape(new));
+            last = m.end();
+        }
+        if last > 0 {
+            buf.push_str(&name[last..]);
+            *name = buf;
+            result = Rename::Done;
+        }
+    }
+    // the regex may still match original domain in some non-literal way,
+    // but catch-all regexes which also match new domain are fine
+    let still_matches = match Regex::new(name) {
+        Ok(re) => domains.iter()
+            .any(|&(orig, new)| re.is_match(orig) && !re.is_match(new)),
+        Err(_) => domains.iter().any(|&(orig, _)| mentions_domain(name, orig)),
+    };
+    if still_matches {
+        return Rename::Unsafe;
+    }
+    result
+}
+
+fn rename_server_name(name: &mut ast::ServerName, domains: &[(&str, &str)])
+    -> Rename
 {
     use nginx_config::ast::ServerName::*;
-    let mut snames = HashMap::new();
+    match *name {
+        Exact(ref mut n) | Suffix(ref mut n) | StarSuffix(ref mut n)
+        => rename_host(n, domains),
+        StarPrefix(ref mut n) => rename_star_prefix(n, domains),
+        Regex(ref mut n) => rename_regex(n, domains),
+    }
+}
+
+fn server_names(cfg: &mut Config, names: &Vec<String>, strict: bool,
+    reporter: &Reporter)
+    -> Result<(), Error>
+{
+    let mut snames = Vec::new();
     for item in names {
         let mut pair = item.splitn(2, '=');
         let orig = pair.next().expect("first item always exists");
         if let Some(dest) = pair.next() {
-            snames.insert(orig, dest);
+            snames.push((orig, dest));
         } else {
             bail!("server name {:?} doesn't include substitution target \
-                   (format is `orig.name=dest.example.org`)");
+                   (format is `orig.name=dest.example.org`)", item);
         }
     }
+    let mut unsafe_names = Vec::new();
     visit_mutable(cfg.directives_mut(), |dir| {
+        if let ast::Item::ServerName(ref mut names) = dir.item {
+            for name in names {
+                if let Rename::Unsafe = rename_server_name(name, &snames) {
+                    unsafe_names.push((dir.position, format!("{:?}", name)));
+                }
+            }
+        }
+    });
+    for (pos, name) in &unsafe_names {
+        reporter.diagnostic(&Diagnostic::at(*pos,
+            format!("can't substitute server name {}", name)));
+    }
+    if strict && !unsafe_names.is_empty() {
+        bail!("some server names can't be substituted");
+    }
+    Ok(())
+}
+
+fn mentions_domain(text: &str, domain: &str) -> bool {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '-';
+    let mut start = 0;
+    while let Some(off) = text[start..].find(domain) {
+        let begin = start + off;
+        let end = begin + domain.len();
+        if !text[..begin].chars().next_back().is_some_and(is_name_char) &&
+           !text[end..].chars().next().is_some_and(is_name_char)
+        {
+            return true;
+        }
+        start = begin + 1;
+    }
+    false
+}
+
+fn is_host_var(value: &Value) -> bool {
+    let var = value.to_string();
+    var == "$host" || var == "$http_host" || var == "$server_name"
+}
+
+struct DomainRenamer<'a> {
+    domains: Vec<(&'a str, &'a str)>,
+    skipped: Vec<(Pos, &'static str, String)>,
+}
+
+impl<'a> DomainRenamer<'a> {
+    fn host(&self, name: &str) -> Option<String> {
+        for &(orig, new) in &self.domains {
+            if let Some(prefix) = relative(name, orig) {
+                return Some(format!("{}{}", prefix, new));
+            }
+        }
+        None
+    }
+    fn url(&self, url: &str) -> Option<String> {
+        for &(orig, new) in &self.domains {
+            if let Some((pre, suf)) = proxy_subst(url, orig) {
+                return Some(format!("{}{}{}", pre, new, suf));
+            }
+        }
+        None
+    }
+    fn check(&mut self, pos: Pos, dir: &'static str, text: &str) {
+        if self.domains.iter().any(|&(orig, _)| mentions_domain(text, orig)) {
+            self.skipped.push((pos, dir, text.to_string()));
+        }
+    }
+    fn rename_value(&mut self, pos: Pos, dir: &'static str,
+        value: &mut Value, bare_host: bool)
+        -> Result<(), Error>
+    {
+        let text = value.to_string();
+        let new = match self.url(&text) {
+            Some(new) => new,
+            None if bare_host => match self.host(&text) {
+                Some(new) => new,
+                None => {
+                    self.check(pos, dir, &text);
+                    return Ok(());
+                }
+            },
+            None => {
+                self.check(pos, dir, &text);
+                return Ok(());
+            }
+        };
+        *value = Value::from_str(&new).map_err(err_msg)?;
+        Ok(())
+    }
+    /// Renames literal compared to the host, other literals are checked
+    fn rename_condition(&mut self, pos: Pos, dir: &'static str,
+        condition: &mut ast::IfCondition)
+    {
+        use nginx_config::ast::IfCondition::*;
+
+        match *condition {
+            Eq(ref var, ref mut text) | Neq(ref var, ref mut text)
+            if is_host_var(var) => {
+                let quoted = text.len() >= 2 &&
+                    (text.starts_with('"') || text.starts_with('\''));
+                let bare = if quoted {
+                    &text[1..text.len()-1]
+                } else {
+                    &text[..]
+                };
+                // `$http_host` may have a port
+                let (host, port) = match bare.rfind(':') {
+                    Some(idx) => bare.split_at(idx),
+                    None => (bare, ""),
+                };
+                let new = self.host(host).map(|new| if quoted {
+                    format!("{0}{1}{2}{0}", &text[..1], new, port)
+                } else {
+                    format!("{}{}", new, port)
+                });
+                match new {
+                    Some(new) => *text = new,
+                    None => self.check(pos, dir, text),
+                }
+            }
+            Eq(_, ref text) | Neq(_, ref text) |
+            RegEq(_, ref text, _) | RegNeq(_, ref text, _)
+            => self.check(pos, dir, text),
+            NonEmpty(ref v) | Exists(ref v) | NotExists(ref v) |
+            FileExists(ref v) | FileNotExists(ref v) |
+            DirExists(ref v) | DirNotExists(ref v) |
+            Executable(ref v) | NotExecutable(ref v)
+            => self.check(pos, dir, &v.to_string()),
+        }
+    }
+    fn rename_directive(&mut self, dir: &mut Directive) -> Result<(), Error> {
+        use nginx_config::ast::MapPattern as M;
+
+        let pos = dir.position;
+        let name = dir.item.directive_name();
         match dir.item {
-            ast::Item::ServerName(ref mut names) => {
-                for name in names {
-                    match *name {
-                        Exact(ref mut n) | Suffix(ref mut n) |
-                        StarSuffix(ref mut n)
-                        => {
-                            for (orig, new) in &snames {
-                                *n = if let Some(prefix) = relative(n, orig) {
-                                    format!("{}{}", prefix, new)
-                                } else {
-                                    continue;
-                                }
-                            }
-                        }
-                        StarPrefix(_) => {}  // ingoring, warn? forbid?
-                        Regex(_) => {}  // ingoring, warn? forbid?
+            Item::ServerName(ref mut names) => {
+                for sname in names {
+                    if let Rename::Unsafe =
+                        rename_server_name(sname, &self.domains)
+                    {
+                        self.skipped.push((pos, name, format!("{:?}", sname)));
                     }
                 }
             }
+            Item::Return(ast::Return::Redirect { ref mut url, .. }) => {
+                self.rename_value(pos, name, url, false)?;
+            }
+            Item::Rewrite(ast::Rewrite { ref mut replacement, .. }) => {
+                self.rename_value(pos, name, replacement, false)?;
+            }
+            Item::ErrorPage(ast::ErrorPage { ref mut uri, .. }) => {
+                self.rename_value(pos, name, uri, false)?;
+            }
+            Item::AddHeader(ast::AddHeader {
+                ref field, ref mut value, ..
+            }) => {
+                let field = field.to_string().to_lowercase();
+                if field == "location" ||
+                   field == "access-control-allow-origin"
+                {
+                    self.rename_value(pos, name, value, false)?;
+                } else {
+                    self.check(pos, name, &value.to_string());
+                }
+            }
+            Item::Map(ast::Map {
+                ref mut patterns, ref mut default, ..
+            }) => {
+                for &mut (ref mut pattern, ref mut value) in patterns {
+                    let result = match *pattern {
+                        M::Exact(ref mut n) | M::Suffix(ref mut n) |
+                        M::StarSuffix(ref mut n)
+                        => rename_host(n, &self.domains),
+                        M::StarPrefix(ref mut n)
+                        => rename_star_prefix(n, &self.domains),
+                        M::Regex(ref mut n)
+                        => rename_regex(n, &self.domains),
+                    };
+                    if let Rename::Unsafe = result {
+                        self.skipped.push((pos, name,
+                            format!("{:?}", pattern)));
+                    }
+                    self.rename_value(pos, name, value, true)?;
+                }
+                if let Some(ref mut value) = *default {
+                    self.rename_value(pos, name, value, true)?;
+                }
+            }
+            // backends are handled by `--subst-proxy-pass-host`
+            Item::ProxyPass(..) => {}
+            Item::If(ast::If { ref mut condition, .. }) => {
+                self.rename_condition(pos, name, condition);
+            }
+            Item::Location(ast::Location { ref pattern, .. }) => {
+                self.check(pos, name, &pattern.to_string());
+            }
+            ref item if item.children().is_none() => {
+                self.check(pos, name, &item.to_string());
+            }
             _ => {}
         }
+        Ok(())
+    }
+}
+
+fn rename_domains(cfg: &mut Config, items: &Vec<String>, strict: bool,
+    reporter: &Reporter)
+    -> Result<(), Error>
+{
+    let mut renamer = DomainRenamer {
+        domains: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for item in items {
+        let mut pair = item.splitn(2, '=');
+        let orig = pair.next().expect("first item always exists");
+        if let Some(dest) = pair.next() {
+            renamer.domains.push((orig, dest));
+        } else {
+            bail!("domain {:?} doesn't include substitution target \
+                   (format is `orig.domain=dest.example.org`)", item);
+        }
+    }
+    let mut err = None;
+    visit_mutable(cfg.directives_mut(), |dir| {
+        if let Err(e) = renamer.rename_directive(dir) {
+            err = Some(e);
+        }
     });
+    if let Some(e) = err {
+        return Err(e);
+    }
+    for (pos, name, text) in &renamer.skipped {
+        reporter.diagnostic(&Diagnostic::at(*pos,
+            format!("can't rename domain in `{}` directive", name))
+            .hint(text.trim_end()));
+    }
+    if strict && renamer.skipped.iter()
+        .any(|&(_, name, _)| name == "server_name" || name == "map")
+    {
+        bail!("some server names can't be renamed");
+    }
     Ok(())
 }
 
@@ -317,6 +734,35 @@ fn if_regexes(cfg: &mut Config, items: &Vec<String>)
     Ok(())
 }
 
+fn subst_url_host(value: &mut Value, regexes: &[(Regex, &str)])
+    -> Result<(), String>
+{
+    let s = value.to_string();
+    if !(s.starts_with("http://") ||
+         s.starts_with("https://") ||
+         s.starts_with("$scheme://"))
+    {
+        return Ok(());
+    }
+    let (prefix, tmp) = s.split_at(s.find('/').unwrap() + 2);
+    let (host, suffix) = if let Some(end) = tmp.find(&['/', '$'][..]) {
+        tmp.split_at(end)
+    } else {
+        (tmp, "")
+    };
+    let mut host = host.to_string();
+    for (regex, repl) in regexes {
+        host = regex.replace(host.as_ref(), *repl).to_string();
+    }
+    let mut s = String::with_capacity(
+        prefix.len() + suffix.len() + host.len());
+    s.push_str(prefix);
+    s.push_str(&host);
+    s.push_str(suffix);
+    *value = Value::from_str(&s)?;
+    Ok(())
+}
+
 fn rewrite_host_regexes(cfg: &mut Config, items: &Vec<String>)
     -> Result<(), Error>
 {
@@ -334,37 +780,21 @@ fn rewrite_host_regexes(cfg: &mut Config, items: &Vec<String>)
     }
     let mut err = None;
     visit_mutable(cfg.directives_mut(), |dir| {
-        match dir.item {
+        let result = match dir.item {
             | ast::Item::Rewrite(ast::Rewrite { ref mut replacement, .. })
-            => {
-                let mut s = replacement.to_string();
-                if !(s.starts_with("http://") ||
-                     s.starts_with("https://") ||
-                     s.starts_with("$scheme://"))
-                {
-                    return;
-                }
-                let (prefix, tmp) = s.split_at(s.find('/').unwrap() + 2);
-                let (host, suffix) = if let Some(end) = tmp.find('/') {
-                    tmp.split_at(end)
-                } else {
-                    (tmp, "")
-                };
-                let mut host = host.to_string();
-                for (regex, repl) in &regexes {
-                    host = regex.replace(host.as_ref(), *repl).to_string();
-                }
-                let mut s = String::with_capacity(
-                    prefix.len() + suffix.len() + host.len());
-                s.push_str(prefix);
-                s.push_str(&host);
-                s.push_str(suffix);
-                match Value::from_str(&s) {
-                    Ok(v) => *replacement = v,
-                    Err(e) => err = Some(e),
-                }
-            }
-            _ => {}
+            => subst_url_host(replacement, &regexes),
+            | ast::Item::Return(ast::Return::Redirect { ref mut url, .. })
+            => subst_url_host(url, &regexes),
+            | ast::Item::ErrorPage(ast::ErrorPage { ref mut uri, .. })
+            => subst_url_host(uri, &regexes),
+            | ast::Item::AddHeader(ast::AddHeader {
+                ref field, ref mut value, ..
+            }) if field.to_string().eq_ignore_ascii_case("location")
+            => subst_url_host(value, &regexes),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            err = Some(e);
         }
     });
     if let Some(e) = err {
@@ -426,53 +856,175 @@ fn replace_listen(dirs: &mut Vec<Directive>, lst: &Vec<Listen>) {
     }
 }
 
-fn expand_local_includes(dest: &mut Vec<Directive>, path: &Path)
-    -> Result<(), Error>
+fn address_port(addr: &Address) -> Option<u16> {
+    match *addr {
+        Address::Ip(sa) => Some(sa.port()),
+        Address::StarPort(port) | Address::Port(port) => Some(port),
+        Address::Unix(..) => None,
+    }
+}
+
+fn normalize_address(addr: &Address) -> Address {
+    match *addr {
+        Address::StarPort(port) | Address::Port(port)
+        => Address::Ip(SocketAddr::from(([0, 0, 0, 0], port))),
+        ref other => other.clone(),
+    }
+}
+
+fn is_ipv6(addr: &Address) -> bool {
+    matches!(*addr, Address::Ip(SocketAddr::V6(..)))
+}
+
+fn map_address(addr: &Address, mapping: &[(Address, Address)])
+    -> Option<Address>
 {
-    use nginx_config::ast::Item::Include;
-
-    let orig_len = dest.len();
-    let source = mem::replace(dest, Vec::with_capacity(orig_len));
-    for mut dir in source {
-        let inc_path = match dir.item {
-            Include(ref inc_path) => {
-                let inc_path = inc_path.to_string();
-                let inc_path = Path::new(&inc_path);
-                if !inc_path.is_absolute() {
-                    Some(path.parent()
-                        .expect("file path always has parent")
-                        .join(inc_path))
-                } else {
-                    None
+    for (from, to) in mapping {
+        match (from, to) {
+            (&Address::Port(port), &Address::Port(new_port))
+            if address_port(addr) == Some(port)
+            => {
+                return Some(match *addr {
+                    Address::Ip(mut sa) => {
+                        sa.set_port(new_port);
+                        Address::Ip(sa)
+                    }
+                    Address::StarPort(_) => Address::StarPort(new_port),
+                    Address::Port(_) => Address::Port(new_port),
+                    Address::Unix(..) => unreachable!(),
+                });
+            }
+            (&Address::Port(port), _) if address_port(addr) == Some(port)
+                && is_ipv6(addr) == is_ipv6(to)
+            => return Some(to.clone()),
+            (&Address::Port(_), _) => {}
+            _ if normalize_address(addr) == normalize_address(from)
+            => return Some(to.clone()),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[derive(Default)]
+struct Ipv6Mirror {
+    default_server: HashSet<u16>,
+    socket_options: HashSet<u16>,
+}
+
+impl Ipv6Mirror {
+    fn new(cfg: &Config) -> Ipv6Mirror {
+        let mut mirror = Ipv6Mirror::default();
+        for dir in cfg.all_directives() {
+            if let ast::Item::Listen(ref lst) = dir.item {
+                if let Some((true, port)) = wildcard_port(&lst.address) {
+                    if lst.default_server {
+                        mirror.default_server.insert(port);
+                    }
+                    if has_socket_options(lst) {
+                        mirror.socket_options.insert(port);
+                    }
                 }
             }
-            _ => {
-                if let Some(list) = dir.item.children_mut() {
-                    expand_local_includes(list, path)?;
+        }
+        mirror
+    }
+    fn mirror(&mut self, dirs: &mut Vec<Directive>) {
+        let ports = dirs.iter().filter_map(|d| match d.item {
+            ast::Item::Listen(ref lst) => wildcard_port(&lst.address),
+            _ => None,
+        }).collect::<HashSet<_>>();
+        let mut idx = 0;
+        while idx < dirs.len() {
+            let mut lst = match dirs[idx].item {
+                ast::Item::Listen(ref lst) => lst.clone(),
+                _ => {
+                    idx += 1;
+                    continue;
                 }
-                None
+            };
+            idx += 1;
+            let port = match wildcard_port(&lst.address) {
+                Some((false, port)) if !ports.contains(&(true, port)) => port,
+                _ => continue,
+            };
+            lst.address = Address::Ip(SocketAddr::from(
+                (Ipv6Addr::UNSPECIFIED, port)));
+            if lst.default_server && !self.default_server.insert(port) {
+                lst.default_server = false;
             }
-        };
-        let inc_path = if let Some(inc_path) = inc_path  {
-            inc_path
-        } else {
-            dest.push(dir);
-            continue;
-        };
-        let contents = read_to_string(&inc_path)
-            .map_err(|e| format_err!("error reading {:?}: {}", inc_path, e))?;
-        let inc_dirs = nginx_config::parse_directives(&contents)?;
-        dest.extend(inc_dirs);
+            if has_socket_options(&lst) && !self.socket_options.insert(port) {
+                lst.setfib = None;
+                lst.fastopen = None;
+                lst.backlog = None;
+                lst.rcvbuf = None;
+                lst.sndbuf = None;
+                lst.deferred = false;
+                lst.bind = false;
+                lst.ipv6only = None;
+                lst.reuseport = false;
+            }
+            dirs.insert(idx, Directive {
+                position: Pos { line: 0, column: 0 },
+                item: ast::Item::Listen(lst),
+            });
+            idx += 1;
+        }
     }
-    Ok(())
+}
+
+fn migrate(cfg: &mut Config, reporter: &Reporter) {
+    visit_mutable(cfg.directives_mut(), |dir| {
+        if let ast::Item::Listen(ref mut lst) = dir.item {
+            if lst.ext == Some(ast::HttpExt::Spdy) {
+                lst.ext = Some(ast::HttpExt::Http2);
+                reporter.diagnostic(&Diagnostic::at(dir.position, format!(
+                    "replaced `spdy` with `http2` in listen {}",
+                    lst.address)).warning());
+            }
+        }
+    });
 }
 
 pub fn run(modify: Modify) -> Result<(), Error> {
-    let mut cfg = Config::partial_file(EntryPoint::Main, &modify.file)?;
+    let dump = if modify.from_dump {
+        Some(report::read_dump(&modify.file)?)
+    } else {
+        None
+    };
+    let (mut cfg, mut reporter) = match dump {
+        Some(ref dump) => {
+            let (cfg, reporter, _) = report::read_dump_config(
+                modify.entry_point, dump, false)?;
+            (cfg, reporter)
+        }
+        None => report::read_config(modify.entry_point, &modify.file)?,
+    };
 
-    if modify.expand_local_includes {
-        expand_local_includes(cfg.directives_mut(), &modify.file)?;
+    let mut allow_list = AllowList::new(&modify.allow_includes);
+    allow_list.canonicalize(modify.canonicalize_includes);
+    let mut expander = match dump {
+        Some(ref dump) => dump.expander(),
+        None => Expander::new(modify.file.parent()
+            .expect("file path always has parent")),
+    };
+    if let Some(ref root) = modify.include_root {
+        expander.include_root(root);
     }
+    if !modify.allow_includes.is_empty() {
+        expander.allow_list(&allow_list);
+    }
+    if modify.expand_local_includes || modify.include_root.is_some() ||
+        dump.is_some()
+    {
+        if let Err(e) = expander.expand(&mut cfg) {
+            reporter.report_include(&e);
+            return Err(err_msg("can't expand includes"));
+        }
+        reporter.origins(&cfg);
+    }
+    policy::check(&cfg, &modify.policy, &modify.allow_includes,
+                  &reporter)?;
 
     // vars
     let mut vars = HashMap::new();
@@ -493,8 +1045,39 @@ pub fn run(modify: Modify) -> Result<(), Error> {
         });
     }
 
+    if !modify.map_listen.is_empty() {
+        visit_mutable(cfg.directives_mut(), |dir| {
+            if let ast::Item::Listen(ref mut lst) = dir.item {
+                if let Some(addr) = map_address(&lst.address,
+                                                &modify.map_listen)
+                {
+                    lst.address = addr;
+                }
+            }
+        });
+    }
+
+    if modify.migrate {
+        migrate(&mut cfg, &reporter);
+    }
+
+    if modify.mirror_ipv6 {
+        let mut mirror = Ipv6Mirror::new(&cfg);
+        visit_mutable(cfg.directives_mut(), |dir| {
+            if let ast::Item::Server(ref mut server) = dir.item {
+                mirror.mirror(&mut server.directives);
+            }
+        });
+    }
+
     if modify.server_name_mapping.len() > 0 {
-        server_names(&mut cfg, &modify.server_name_mapping)?;
+        server_names(&mut cfg, &modify.server_name_mapping,
+            modify.strict_server_names, &reporter)?;
+    }
+
+    if !modify.rename_domain.is_empty() {
+        rename_domains(&mut cfg, &modify.rename_domain,
+            modify.strict_server_names, &reporter)?;
     }
 
     if modify.proxy_pass_mapping.len() > 0 {
@@ -511,18 +1094,10 @@ pub fn run(modify: Modify) -> Result<(), Error> {
         rewrite_host_regexes(&mut cfg, &modify.rewrite_host_regexes)?;
     }
 
-    for dir in cfg.all_directives() {
-        match &dir.item {
-            Item::Include(path) => {
-                let path = path.to_string();
-                let path = Path::new(&path);
-                if !modify.allow_includes.iter().any(|p| path.starts_with(p)) {
-                    bail!("invalid include path {:?}, allowed: {:?}",
-                        path, &modify.allow_includes);
-                }
-            }
-            _ => {}
-        }
+    if let Err(e) = expander.check(&cfg, &allow_list) {
+        reporter.diagnostic(&e.to_diagnostic()
+            .hint(format!("allowed: {:?}", &modify.allow_includes)));
+        return Err(err_msg("include is not allowed"));
     }
 
     if modify.replace_by_name.len() > 0 {
@@ -544,13 +1119,142 @@ pub fn run(modify: Modify) -> Result<(), Error> {
             !regexes.iter().any(|x| x.is_match(dom))
         });
         if let Err(errs) = result {
-            for e in errs {
-                error!("{}", e);
-            }
+            reporter.report_all(&errs);
             return Err(err_msg("failed to resolve some hostnames"));
         }
     }
 
-    print!("{}", cfg);
+    print!("{}", cfg.to_string_indented(4));
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use nginx_config::ast;
+    use nginx_config::visitors::visit_mutable;
+    use nginx_config_mod::{Config, EntryPoint};
+
+    use super::{DomainRenamer, Ipv6Mirror, map_address, parse_address};
+    use super::parse_listen_mapping;
+
+    fn map(mapping: &[&str], addr: &str) -> Option<String> {
+        let mapping = mapping.iter()
+            .map(|m| parse_listen_mapping(m).unwrap())
+            .collect::<Vec<_>>();
+        let addr = parse_address(addr).unwrap();
+        map_address(&addr, &mapping).map(|a| a.to_string())
+    }
+
+    fn mirror(text: &str) -> String {
+        let mut cfg = Config::parse_str(EntryPoint::Http, text, "a")
+            .unwrap();
+        let mut mirror = Ipv6Mirror::new(&cfg);
+        visit_mutable(cfg.directives_mut(), |dir| {
+            if let ast::Item::Server(ref mut server) = dir.item {
+                mirror.mirror(&mut server.directives);
+            }
+        });
+        cfg.to_string_indented(4)
+    }
+
+    fn rename(text: &str) -> (String, usize) {
+        let mut cfg = Config::parse_str(EntryPoint::Server, text, "a")
+            .unwrap();
+        let mut renamer = DomainRenamer {
+            domains: vec![("example.com", "example.org")],
+            skipped: Vec::new(),
+        };
+        visit_mutable(cfg.directives_mut(), |dir| {
+            renamer.rename_directive(dir).unwrap();
+        });
+        (cfg.to_string_indented(4), renamer.skipped.len())
+    }
+
+    #[test]
+    fn rename_if_host() {
+        assert_eq!(rename("if ($host = www.example.com) { return 403; }"),
+                   ("if ($host = www.example.org) {\n    return 403;\n}\n"
+                    .to_string(), 0));
+        assert_eq!(rename("if ($http_host != 'example.com:8080') \
+                           { return 403; }"),
+                   ("if ($http_host != example.org:8080) {\n    \
+                     return 403;\n}\n".to_string(), 0));
+        assert_eq!(rename("if ($host = example.net) { return 403; }").1, 0);
+    }
+
+    #[test]
+    fn check_if() {
+        // other variables may mean anything, they are only reported
+        assert_eq!(rename("if ($arg_a = example.com) { return 403; }"),
+                   ("if ($arg_a = example.com) {\n    return 403;\n}\n"
+                    .to_string(), 1));
+        assert_eq!(rename("if ($host ~ ^www.example.com$) { return 403; }")
+                   .1, 1);
+    }
+
+    #[test]
+    fn listen_mapping() {
+        let pair = |s| parse_listen_mapping(s)
+            .map(|(a, b)| (a.to_string(), b.to_string()));
+        assert_eq!(pair("80=8080").unwrap(),
+                   ("80".to_string(), "8080".to_string()));
+        assert_eq!(pair("0.0.0.0:443=127.0.0.1:8443").unwrap(),
+                   ("0.0.0.0:443".to_string(), "127.0.0.1:8443".to_string()));
+        assert_eq!(pair("[::]:443=[::1]:8443").unwrap(),
+                   ("[::]:443".to_string(), "[::1]:8443".to_string()));
+        assert!(pair("80").is_err());
+        assert!(pair("80=").is_err());
+        assert!(pair("x:y=80").is_err());
+        assert!(pair("80 ssl=8080").is_err());
+    }
+
+    #[test]
+    fn map_listen() {
+        assert_eq!(map(&["80=8080"], "80"), Some("8080".to_string()));
+        assert_eq!(map(&["80=8080"], "127.0.0.1:80"),
+                   Some("127.0.0.1:8080".to_string()));
+        assert_eq!(map(&["80=8080"], "[::]:80"),
+                   Some("[::]:8080".to_string()));
+        assert_eq!(map(&["443=127.0.0.1:8443"], "443"),
+                   Some("127.0.0.1:8443".to_string()));
+        // ipv6 listens are not mapped to ipv4 address
+        assert_eq!(map(&["443=127.0.0.1:8443"], "[::]:443"), None);
+        assert_eq!(map(&["0.0.0.0:443=127.0.0.1:8443"], "443"),
+                   Some("127.0.0.1:8443".to_string()));
+        assert_eq!(map(&["[::]:443=[::1]:8443"], "[::]:443"),
+                   Some("[::1]:8443".to_string()));
+    }
+
+    #[test]
+    fn unmatched_listen() {
+        assert_eq!(map(&["80=8080"], "81"), None);
+        assert_eq!(map(&["80=8080"], "unix:/run/a.sock"), None);
+        assert_eq!(map(&["10.0.0.1:80=8080"], "10.0.0.2:80"), None);
+        assert_eq!(map(&["[::1]:80=8080"], "[::2]:80"), None);
+    }
+
+    #[test]
+    fn mirror_ipv6() {
+        assert_eq!(mirror("server { listen 80; listen 10.0.0.1:81; }"),
+                   "server {\n    listen 80;\n    listen [::]:80;\n    \
+                    listen 10.0.0.1:81;\n}\n");
+        // options nginx allows once per address are not copied twice
+        assert_eq!(mirror("server { listen 80 default_server reuseport; } \
+                           server { listen 80 default_server reuseport; } \
+                           server { listen [::]:80 default_server; }"),
+                   "server {\n    listen 80 default_server reuseport;\n    \
+                    listen [::]:80 reuseport;\n}\n\n\
+                    server {\n    listen 80 default_server reuseport;\n    \
+                    listen [::]:80;\n}\n\n\
+                    server {\n    listen [::]:80 default_server;\n}\n");
+    }
+
+    #[test]
+    fn mirror_existing() {
+        let text = "server {\n    listen 80;\n    listen [::]:80;\n}\n";
+        assert_eq!(mirror(text), text);
+        let text = "server {\n    listen 443 ssl;\n    \
+                    listen [::]:443 ssl ipv6only=on;\n}\n";
+        assert_eq!(mirror(text), text);
+    }
+}
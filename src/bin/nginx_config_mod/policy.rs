@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use failure::{Error, err_msg};
+use nginx_config_mod::Config;
+use nginx_config_mod::checks::policy::{Policy, check_policy};
+
+use report::Reporter;
+
+#[derive(StructOpt)]
+pub struct PolicyOptions {
+    #[structopt(long="sandbox", help="\
+        Check config as an untrusted snippet: deny directives which run \
+        code or change environment (`perl`, `lua_*`, `js_*`, \
+        `load_module`, `env`), and allow only paths and proxy hosts \
+        enabled by `--allow-path` and `--allow-proxy-host`. Includes \
+        left in the config after expanding are denied, unless allowed \
+        by `--allow-includes` of the `modify` command.")]
+    sandbox: bool,
+
+    #[structopt(long="deny-directive", name="DIRECTIVE", help="\
+        Deny directive by name, `*` matches any characters \
+        (e.g. `ssl_*`).")]
+    deny_directive: Vec<String>,
+
+    #[structopt(long="allow-path", name="PATH_PREFIX", help="\
+        Allow paths with this prefix in `root`, `alias`, `access_log`, \
+        `error_log`, `ssl_certificate` and `ssl_certificate_key`. \
+        As soon as this option is used, all other paths are denied.",
+        parse(from_os_str))]
+    allow_path: Vec<PathBuf>,
+
+    #[structopt(long="allow-proxy-host", name="HOST", help="\
+        Allow `proxy_pass` to this host, `.example.com` also allows \
+        all subdomains. As soon as this option is used, all other hosts \
+        are denied.")]
+    allow_proxy_host: Vec<String>,
+}
+
+impl PolicyOptions {
+    fn policy(&self, allowed_includes: &[PathBuf]) -> Option<Policy> {
+        let mut policy = if self.sandbox {
+            Policy::sandbox()
+        } else if !self.deny_directive.is_empty() ||
+            !self.allow_path.is_empty() || !self.allow_proxy_host.is_empty()
+        {
+            Policy::new()
+        } else {
+            return None;
+        };
+        for name in &self.deny_directive {
+            policy.deny_directive(name);
+        }
+        for path in &self.allow_path {
+            policy.allow_path(path);
+        }
+        for host in &self.allow_proxy_host {
+            policy.allow_proxy_host(host);
+        }
+        if self.sandbox {
+            for prefix in allowed_includes {
+                policy.allow_include(prefix);
+            }
+        }
+        Some(policy)
+    }
+}
+
+/// Checks config against the policy
+///
+/// In the sandbox, includes matching `allowed_includes` are allowed.
+pub fn check(cfg: &Config, options: &PolicyOptions,
+    allowed_includes: &[PathBuf], reporter: &Reporter)
+    -> Result<(), Error>
+{
+    let policy = match options.policy(allowed_includes) {
+        Some(policy) => policy,
+        None => return Ok(()),
+    };
+    if let Err(errs) = check_policy(cfg, &policy) {
+        reporter.report_all(&errs);
+        return Err(err_msg("config violates the policy"));
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use std::env;
+use std::fs::read_to_string;
+use std::io::{self, Read, IsTerminal};
+use std::path::Path;
+
+use failure::{Error, ResultExt, err_msg};
+use nginx_config_mod::{Config, EntryPoint};
+use nginx_config_mod::diagnostics::{Diagnostic, Emitter, ToDiagnostic};
+use nginx_config_mod::dump::Dump;
+use nginx_config_mod::includes::{self, Origin, Origins};
+
+/// Prints diagnostics for a config file to stderr
+pub struct Reporter {
+    name: String,
+    source: Option<String>,
+    color: bool,
+    dump: Option<Dump>,
+    origins: Origins,
+}
+
+/// Name of the standard input in diagnostics
+pub const STDIN_NAME: &str = "<stdin>";
+
+impl Reporter {
+    /// Reporter for the config, `source` is its text if it's known
+    pub fn with_source(name: &str, source: Option<String>) -> Reporter {
+        Reporter {
+            name: name.to_string(),
+            source,
+            color: io::stderr().is_terminal() &&
+                env::var_os("NO_COLOR").is_none(),
+            dump: None,
+            origins: Origins::default(),
+        }
+    }
+    /// Show directives of included files with their own file and text
+    ///
+    /// Should be called after includes of the config are expanded.
+    pub fn origins(&mut self, cfg: &Config) -> &mut Self {
+        self.origins = cfg.origins().clone();
+        self
+    }
+    /// Reporter for the main file of the dump
+    ///
+    /// Errors in included files show source from the dump too.
+    pub fn for_dump(dump: &Dump) -> Reporter {
+        let mut reporter = Reporter::for_file(dump, dump.main_file());
+        reporter.dump = Some(dump.clone());
+        reporter
+    }
+    fn for_file(dump: &Dump, path: &Path) -> Reporter {
+        Reporter::with_source(&path.display().to_string(),
+                              dump.get(path).map(|s| s.to_string()))
+    }
+    pub fn diagnostic(&self, diag: &Diagnostic) {
+        let origin = diag.position()
+            .map(|pos| self.origins.find(pos))
+            .unwrap_or(Origin::Main);
+        let (name, source) = match origin {
+            Origin::Main => (self.name.clone(), self.source.as_deref()),
+            Origin::Included(path, text) => {
+                (path.display().to_string(), Some(text))
+            }
+            // snippet of a wrong file is worse than none
+            Origin::Unknown => {
+                (format!("{} or an included file", self.name), None)
+            }
+        };
+        let mut emitter = Emitter::new(&name, source);
+        emitter.color(self.color);
+        eprint!("{}", emitter.render(diag));
+    }
+    pub fn report<E: ToDiagnostic>(&self, err: &E) {
+        self.diagnostic(&err.to_diagnostic());
+    }
+    pub fn report_all<E: ToDiagnostic>(&self, errs: &[E]) {
+        for e in errs {
+            self.report(e);
+        }
+    }
+    /// Reports include error in the file it happened in
+    ///
+    /// Syntax errors are shown for the included file, other errors for
+    /// the file having the include directive.
+    pub fn report_include(&self, err: &includes::Error) {
+        match *err {
+            includes::Error::Syntax(ref path, _) |
+            includes::Error::Include(Some(ref path), ..)
+            => match self.dump {
+                Some(ref dump) => Reporter::for_file(dump, path).report(err),
+                None => Reporter::with_source(&path.display().to_string(),
+                                              read_to_string(path).ok())
+                    .report(err),
+            },
+            _ => self.report(err),
+        }
+    }
+}
+
+/// Returns true if path means standard input
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+fn read_stdin() -> Result<String, Error> {
+    let mut buf = String::with_capacity(1024);
+    io::stdin().read_to_string(&mut buf)
+        .context("error reading standard input")?;
+    Ok(buf)
+}
+
+/// Reads config reporting syntax errors as diagnostics
+///
+/// Path `-` means standard input.
+pub fn read_config(entry_point: EntryPoint, path: &Path)
+    -> Result<(Config, Reporter), Error>
+{
+    let text = read_input(path)?;
+    let (result, reporter) = if is_stdin(path) {
+        let result = Config::parse_str(entry_point, &text, STDIN_NAME);
+        (result, Reporter::with_source(STDIN_NAME, Some(text)))
+    } else {
+        let result = Config::partial_str(entry_point, &text, path);
+        (result, Reporter::with_source(&path.display().to_string(),
+                                       Some(text)))
+    };
+    match result {
+        Ok(cfg) => Ok((cfg, reporter)),
+        Err(e) => {
+            reporter.report(&e);
+            Err(err_msg("can't read config"))
+        }
+    }
+}
+
+/// Reads config reporting all syntax errors as diagnostics
+///
+/// Returns config with statements that could be parsed and the number of
+/// syntax errors. Path `-` means standard input.
+pub fn read_config_recovering(entry_point: EntryPoint, path: &Path)
+    -> Result<(Config, Reporter, usize), Error>
+{
+    let text = read_input(path)?;
+    let (cfg, errors, reporter) = if is_stdin(path) {
+        let (cfg, errors) = Config::parse_str_recovering(entry_point, &text,
+                                                         STDIN_NAME);
+        (cfg, errors, Reporter::with_source(STDIN_NAME, Some(text)))
+    } else {
+        let (cfg, errors) = Config::partial_str_recovering(entry_point,
+                                                           &text, path);
+        (cfg, errors, Reporter::with_source(&path.display().to_string(),
+                                            Some(text)))
+    };
+    reporter.report_all(&errors);
+    Ok((cfg, reporter, errors.len()))
+}
+
+/// Reads the whole file, path `-` means standard input
+pub fn read_input(path: &Path) -> Result<String, Error> {
+    if is_stdin(path) {
+        read_stdin()
+    } else {
+        Ok(read_to_string(path)
+            .with_context(|_| format!("error reading {:?}", path))?)
+    }
+}
+
+/// Reads output of `nginx -T`, path `-` means standard input
+pub fn read_dump(path: &Path) -> Result<Dump, Error> {
+    Ok(Dump::parse(&read_input(path)?)?)
+}
+
+/// Reads main file of the dump reporting syntax errors as diagnostics
+///
+/// Includes are not expanded. Returns the number of syntax errors, which
+/// is non-zero only if `recover` is set.
+pub fn read_dump_config(entry_point: EntryPoint, dump: &Dump, recover: bool)
+    -> Result<(Config, Reporter, usize), Error>
+{
+    let reporter = Reporter::for_dump(dump);
+    if recover {
+        let (cfg, errors) = dump.main_config_recovering(entry_point);
+        for e in &errors {
+            reporter.report_include(e);
+        }
+        return Ok((cfg, reporter, errors.len()));
+    }
+    match dump.main_config(entry_point) {
+        Ok(cfg) => Ok((cfg, reporter, 0)),
+        Err(e) => {
+            reporter.report_include(&e);
+            Err(err_msg("can't read config"))
+        }
+    }
+}
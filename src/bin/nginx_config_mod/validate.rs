@@ -1,13 +1,66 @@
 use std::path::PathBuf;
 
 use failure::{Error, err_msg};
-use nginx_config_mod::{Config, EntryPoint, checks};
+use nginx_config_mod::{EntryPoint, checks};
+use nginx_config_mod::checks::version::Version;
+use nginx_config_mod::includes::Expander;
+
+use policy::{self, PolicyOptions};
+use report;
 
 #[derive(StructOpt)]
 pub struct Validate {
-    #[structopt(parse(from_os_str))]
+    #[structopt(parse(from_os_str),
+                help="Config file, `-` to read standard input")]
     file: PathBuf,
 
+    #[structopt(long="recover", help="\
+        Don't stop on the first syntax error: skip the statement that \
+        has it, report all syntax errors and run other checks on the \
+        rest of the config. \
+        ")]
+    recover: bool,
+
+    #[structopt(long="from-dump", help="\
+        The file is output of `nginx -T`: check the first file in it with \
+        all includes expanded from the other files of the dump. \
+        ")]
+    from_dump: bool,
+
+    #[structopt(long="expand-local-includes", help="\
+        Read relative includes (resolved against the directory of the \
+        file) and check them too. \
+        ")]
+    expand_local_includes: bool,
+
+    #[structopt(long="entry-point", name="CONTEXT", default_value="main",
+                help="\
+        Context the file is included in: `main`, `http`, `server` or \
+        `location`. Use it to validate snippets. \
+        ")]
+    entry_point: EntryPoint,
+
+    #[structopt(long="check-context", help="\
+        Check that every directive is allowed in the context it's \
+        placed in, e.g. no `listen` in a location and no `location` \
+        at the top level of the main file. \
+        ")]
+    check_context: bool,
+
+    #[structopt(long="check-schema", help="\
+        Check number and types of arguments of known directives (flags, \
+        sizes, time intervals, enumerations). Implies `--check-context`. \
+        ")]
+    check_schema: bool,
+
+    #[structopt(long="nginx-version", name="VERSION", help="\
+        Check that config works with this version of nginx: report \
+        directives and parameters which are not available in it yet or \
+        were removed already, and deprecated ones (e.g. `spdy` or \
+        `http2` parameters of `listen`). \
+        ")]
+    nginx_version: Option<Version>,
+
     #[structopt(long="check-proxy-pass-hostnames", help="\
         Also check that all hostnames in proxy_pass directives can be \
         resolved. This is needed because nginx refuses to start if can't \
@@ -15,17 +68,108 @@ pub struct Validate {
         Note: this might be slow. \
         ")]
     check_proxy_pass_hostnames: bool,
+
+    #[structopt(long="check-listen", help="\
+        Check listen directives of all servers for conflicts: duplicate \
+        listen options and default servers which nginx refuses to start \
+        with, mixing ssl and non-ssl listen on the same address and \
+        duplicate server names on the same address. \
+        ")]
+    check_listen: bool,
+
+    #[structopt(long="check-ipv6-listen", help="\
+        Check that every server listens on the same ports on IPv4 and \
+        IPv6 wildcard addresses (e.g. both `80` and `[::]:80`). \
+        ")]
+    check_ipv6_listen: bool,
+
+    #[structopt(flatten)]
+    policy: PolicyOptions,
 }
 
 pub fn run(validate: Validate) -> Result<(), Error> {
-    let cfg = Config::partial_file(EntryPoint::Main, &validate.file)?;
+    let dump = if validate.from_dump {
+        Some(report::read_dump(&validate.file)?)
+    } else {
+        None
+    };
+    let (mut cfg, mut reporter, mut syntax_errors) =
+        if let Some(ref dump) = dump
+    {
+        report::read_dump_config(validate.entry_point, dump,
+                                 validate.recover)?
+    } else if validate.recover {
+        report::read_config_recovering(validate.entry_point, &validate.file)?
+    } else {
+        let (cfg, reporter) = report::read_config(validate.entry_point,
+                                                  &validate.file)?;
+        (cfg, reporter, 0)
+    };
+    if validate.expand_local_includes || dump.is_some() {
+        let expander = match dump {
+            Some(ref dump) => dump.expander(),
+            None => Expander::new(validate.file.parent()
+                .expect("file path always has parent")),
+        };
+        let result = if validate.recover {
+            expander.expand_recovering(&mut cfg)
+        } else {
+            expander.expand(&mut cfg).map(|()| Vec::new())
+        };
+        match result {
+            Ok(errs) => {
+                for e in &errs {
+                    reporter.report_include(e);
+                }
+                syntax_errors += errs.len();
+                reporter.origins(&cfg);
+            }
+            Err(e) => {
+                reporter.report_include(&e);
+                return Err(err_msg("can't expand includes"));
+            }
+        }
+    }
+    policy::check(&cfg, &validate.policy, &[], &reporter)?;
+    if validate.check_context || validate.check_schema {
+        if let Err(errs) = checks::context::check_context(&cfg) {
+            reporter.report_all(&errs);
+            return Err(err_msg("directives in wrong context"));
+        }
+    }
+    if validate.check_schema {
+        if let Err(errs) = checks::schema::check_schema(&cfg) {
+            reporter.report_all(&errs);
+            return Err(err_msg("invalid directive arguments"));
+        }
+    }
+    if let Some(version) = validate.nginx_version {
+        if let Err(errs) = checks::version::check_version(&cfg, version) {
+            reporter.report_all(&errs);
+            return Err(format_err!(
+                "config is not compatible with nginx {}", version));
+        }
+    }
     if validate.check_proxy_pass_hostnames {
         if let Err(errs) = checks::proxy_pass::check_hostnames(&cfg) {
-            for e in errs {
-                error!("{}", e);
-            }
+            reporter.report_all(&errs);
             return Err(err_msg("failed to resolve some hostnames"));
         }
     }
+    if validate.check_listen {
+        if let Err(errs) = checks::listen::check_listen(&cfg) {
+            reporter.report_all(&errs);
+            return Err(err_msg("conflicting listen directives"));
+        }
+    }
+    if validate.check_ipv6_listen {
+        if let Err(errs) = checks::listen::check_ipv6_parity(&cfg) {
+            reporter.report_all(&errs);
+            return Err(err_msg("IPv4 and IPv6 listen directives differ"));
+        }
+    }
+    if syntax_errors > 0 {
+        return Err(format_err!("{} syntax error(s) found", syntax_errors));
+    }
     Ok(())
 }
@@ -0,0 +1,347 @@
+//! Building directives from code
+//!
+//! Builders quote arguments as needed and check that the result can be
+//! parsed back, so the directives may be added to the config using
+//! `Config::directives_mut` and written with `Display`. Errors are
+//! returned from `build`, so calls may be chained.
+//!
+//! There is no builder for `upstream`, as the parser doesn't support
+//! `upstream` blocks yet.
+use nginx_config::ast::{self, Directive, Item, Listen, LocationPattern};
+use nginx_config::{ParseError, Pos};
+use errors::syntax_error;
+use recover::parse_directives;
+
+
+#[derive(Fail, Debug, Clone)]
+pub enum Error {
+    #[fail(display="argument {:?} can't be quoted", _0)]
+    Quote(String),
+    #[fail(display="invalid directive {:?}: {}", _0, _1)]
+    Invalid(String, String),
+}
+
+/// Builds a `server` block
+#[derive(Debug, Clone, Default)]
+pub struct ServerBuilder {
+    directives: Vec<Directive>,
+    error: Option<Error>,
+}
+
+/// Builds a `location` block
+///
+/// Locations can be nested using [`location`](#method.location).
+#[derive(Debug, Clone)]
+pub struct LocationBuilder {
+    pattern: LocationPattern,
+    directives: Vec<Directive>,
+    error: Option<Error>,
+}
+
+/// Quotes argument if needed
+///
+/// Arguments having double quotes are written in single quotes. Quoted
+/// strings can't have backslashes, so arguments having them are written
+/// as is if special characters are escaped. Fails if argument can't be
+/// written so that the parser reads it back, e.g. it contains both kinds
+/// of quotes or an unescaped space along with a backslash.
+pub fn quote(arg: &str) -> Result<String, Error> {
+    let mut chars = arg.chars().peekable();
+    let mut plain = !arg.is_empty() && !arg.starts_with('#');
+    let mut braces = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '$' if chars.peek() == Some(&'{') => {
+                braces = true;
+                while chars.next().is_some_and(|c| c != '}') {}
+            }
+            ';' | '{' | '}' | '"' | '\'' => plain = false,
+            _ if c.is_whitespace() => plain = false,
+            _ => {}
+        }
+    }
+    if plain || escaped(arg) {
+        return Ok(arg.to_string());
+    }
+    // parser supports neither escapes nor `${name}` in quoted strings
+    if braces || arg.contains('\\') {
+        return Err(Error::Quote(arg.to_string()));
+    }
+    match (arg.contains('"'), arg.contains('\'')) {
+        (false, _) => Ok(format!("\"{}\"", arg)),
+        (true, false) => Ok(format!("'{}'", arg)),
+        (true, true) => Err(Error::Quote(arg.to_string())),
+    }
+}
+
+/// Returns true if all special characters are escaped with a backslash
+fn escaped(arg: &str) -> bool {
+    if arg.is_empty() || arg.starts_with('#') {
+        return false;
+    }
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next().is_none() => return false,
+            ';' | '{' | '}' | '"' | '\'' => return false,
+            _ if c.is_whitespace() => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Creates a directive which has no block
+pub fn directive(name: &str, args: &[&str]) -> Result<Directive, Error> {
+    let mut text = quote(name)?;
+    for arg in args {
+        text.push(' ');
+        text.push_str(&quote(arg)?);
+    }
+    text.push(';');
+    let dir = parse_directives(&text)
+        .map_err(|e| invalid(&text, e))?
+        .pop().expect("single directive is parsed");
+    Ok(Directive {
+        position: no_position(),
+        item: dir.item,
+    })
+}
+
+fn no_position() -> Pos {
+    Pos { line: 0, column: 0 }
+}
+
+/// Checks that directive is formatted into something the parser accepts
+fn check(dir: Directive) -> Result<Directive, Error> {
+    let text = dir.item.to_string();
+    parse_directives(&text).map_err(|e| invalid(&text, e))?;
+    Ok(dir)
+}
+
+fn invalid(text: &str, err: ParseError) -> Error {
+    let message = match syntax_error(&err) {
+        Some((_, details)) => details,
+        None => err.to_string(),
+    };
+    Error::Invalid(text.to_string(), message)
+}
+
+fn add(directives: &mut Vec<Directive>, error: &mut Option<Error>,
+    result: Result<Directive, Error>)
+{
+    match result {
+        Ok(dir) => directives.push(dir),
+        Err(e) => if error.is_none() {
+            *error = Some(e);
+        },
+    }
+}
+
+fn pattern(pattern: LocationPattern) -> Result<LocationPattern, Error> {
+    use nginx_config::ast::LocationPattern::*;
+    // parser keeps quotes in patterns
+    Ok(match pattern {
+        Prefix(p) => Prefix(quote(&p)?),
+        Exact(p) => Exact(quote(&p)?),
+        FinalPrefix(p) => FinalPrefix(quote(&p)?),
+        Regex(p) => Regex(quote(&p)?),
+        RegexInsensitive(p) => RegexInsensitive(quote(&p)?),
+        Named(name) => Named(name),
+    })
+}
+
+impl ServerBuilder {
+    pub fn new() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+    pub fn listen(&mut self, listen: Listen) -> &mut Self {
+        self.push(Directive {
+            position: no_position(),
+            item: Item::Listen(listen),
+        })
+    }
+    pub fn server_name(&mut self, names: &[&str]) -> &mut Self {
+        self.directive("server_name", names)
+    }
+    pub fn root(&mut self, path: &str) -> &mut Self {
+        self.directive("root", &[path])
+    }
+    pub fn ssl_certificate(&mut self, path: &str) -> &mut Self {
+        self.directive("ssl_certificate", &[path])
+    }
+    pub fn ssl_certificate_key(&mut self, path: &str) -> &mut Self {
+        self.directive("ssl_certificate_key", &[path])
+    }
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.directive("add_header", &[name, value])
+    }
+    pub fn location(&mut self, location: &LocationBuilder) -> &mut Self {
+        let result = location.build();
+        add(&mut self.directives, &mut self.error, result);
+        self
+    }
+    /// Adds any directive supported by the parser, without a block
+    pub fn directive(&mut self, name: &str, args: &[&str]) -> &mut Self {
+        add(&mut self.directives, &mut self.error, directive(name, args));
+        self
+    }
+    /// Adds already built directive
+    pub fn push(&mut self, directive: Directive) -> &mut Self {
+        self.directives.push(directive);
+        self
+    }
+    /// Returns the `server` directive or the first error
+    pub fn build(&self) -> Result<Directive, Error> {
+        if let Some(ref e) = self.error {
+            return Err(e.clone());
+        }
+        check(Directive {
+            position: no_position(),
+            item: Item::Server(ast::Server {
+                position: (no_position(), no_position()),
+                directives: self.directives.clone(),
+            }),
+        })
+    }
+}
+
+impl LocationBuilder {
+    /// Creates location, strings in the pattern are quoted as needed
+    pub fn new(pattern: LocationPattern) -> LocationBuilder {
+        LocationBuilder {
+            pattern,
+            directives: Vec::new(),
+            error: None,
+        }
+    }
+    pub fn root(&mut self, path: &str) -> &mut Self {
+        self.directive("root", &[path])
+    }
+    pub fn alias(&mut self, path: &str) -> &mut Self {
+        self.directive("alias", &[path])
+    }
+    pub fn proxy_pass(&mut self, url: &str) -> &mut Self {
+        self.directive("proxy_pass", &[url])
+    }
+    pub fn proxy_set_header(&mut self, name: &str, value: &str)
+        -> &mut Self
+    {
+        self.directive("proxy_set_header", &[name, value])
+    }
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.directive("add_header", &[name, value])
+    }
+    pub fn try_files(&mut self, files: &[&str]) -> &mut Self {
+        self.directive("try_files", files)
+    }
+    pub fn location(&mut self, location: &LocationBuilder) -> &mut Self {
+        let result = location.build();
+        add(&mut self.directives, &mut self.error, result);
+        self
+    }
+    /// Adds any directive supported by the parser, without a block
+    pub fn directive(&mut self, name: &str, args: &[&str]) -> &mut Self {
+        add(&mut self.directives, &mut self.error, directive(name, args));
+        self
+    }
+    /// Adds already built directive
+    pub fn push(&mut self, directive: Directive) -> &mut Self {
+        self.directives.push(directive);
+        self
+    }
+    /// Returns the `location` directive or the first error
+    pub fn build(&self) -> Result<Directive, Error> {
+        if let Some(ref e) = self.error {
+            return Err(e.clone());
+        }
+        check(Directive {
+            position: no_position(),
+            item: Item::Location(ast::Location {
+                position: (no_position(), no_position()),
+                pattern: pattern(self.pattern.clone())?,
+                directives: self.directives.clone(),
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nginx_config::ast::LocationPattern;
+    use recover::parse_directives;
+    use args::args;
+    use super::{quote, directive, ServerBuilder, LocationBuilder, Error};
+
+    fn quoted(arg: &str) -> Option<String> {
+        quote(arg).ok()
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(quoted("/var/www"), Some("/var/www".into()));
+        assert_eq!(quoted("$uri"), Some("$uri".into()));
+        assert_eq!(quoted("${x}y"), Some("${x}y".into()));
+        assert_eq!(quoted("a#b"), Some("a#b".into()));
+    }
+
+    #[test]
+    fn quoted_args() {
+        assert_eq!(quoted(""), Some("\"\"".into()));
+        assert_eq!(quoted("#a"), Some("\"#a\"".into()));
+        assert_eq!(quoted("a;b"), Some("\"a;b\"".into()));
+        assert_eq!(quoted("{}"), Some("\"{}\"".into()));
+        assert_eq!(quoted("a b\tc"), Some("\"a b\tc\"".into()));
+        assert_eq!(quoted("it's"), Some("\"it's\"".into()));
+        assert_eq!(quoted("say \"hi\""), Some("'say \"hi\"'".into()));
+        assert_eq!(quoted("a\\ b\\\""), Some("a\\ b\\\"".into()));
+    }
+
+    #[test]
+    fn unquotable() {
+        assert_eq!(quoted("${x} y"), None);
+        assert_eq!(quoted("a\\ b c"), None);
+        assert_eq!(quoted("'a' \"b\""), None);
+    }
+
+    #[test]
+    fn reparse() {
+        for arg in &["", "#a", "a;b", "{}", "a b", "it's", "say \"hi\"",
+                     "$host:$port", "a\\ b"]
+        {
+            let dir = directive("add_header", &["X-A", arg]).unwrap();
+            assert_eq!(args(&dir.item), vec!["X-A", arg]);
+        }
+    }
+
+    #[test]
+    fn server() {
+        let server = ServerBuilder::new()
+            .directive("listen", &["80"])
+            .server_name(&["example.com", "www.example.com"])
+            .root("/var/www/a b")
+            .location(LocationBuilder::new(
+                    LocationPattern::Prefix("/api v1".into()))
+                .proxy_pass("http://backend")
+                .proxy_set_header("Host", "$host"))
+            .build().unwrap();
+        let text = server.item.to_string();
+        let dirs = parse_directives(&text).unwrap();
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].item.to_string(), text);
+        assert!(text.contains("root \"/var/www/a b\";"));
+        assert!(text.contains("location \"/api v1\" {"));
+    }
+
+    #[test]
+    fn first_error() {
+        let result = ServerBuilder::new()
+            .root("${x} y")
+            .add_header("X-A", "'a' \"b\"")
+            .build();
+        match result {
+            Err(Error::Quote(ref arg)) => assert_eq!(arg, "${x} y"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
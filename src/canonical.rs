@@ -0,0 +1,191 @@
+//! Canonical form of the config
+//!
+//! Configs which differ only in formatting, comments, quoting or order of
+//! directives where order doesn't matter have the same canonical form.
+//!
+//! Directives of a block are sorted by name, keeping relative order of
+//! directives having the same name. The rewrite module directives
+//! (`rewrite`, `return`, `set`, `if`, `break`), access rules (`allow`,
+//! `deny`) and regex locations are executed in order, so they are kept in
+//! order among each other. Blocks having an `include` and entries of
+//! `map` are not sorted.
+//!
+//! The text is built from the AST and written by this crate, so it doesn't
+//! depend on how `nginx_config` formats configs. Any change of the text
+//! for the same config must bump the [`VERSION`](constant.VERSION.html),
+//! which is a part of the hash.
+use printer::{self, Layout, Node};
+use Config;
+
+
+/// Version of the canonical text, hashes of different versions differ
+const VERSION: u32 = 1;
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Returns canonical text of the config
+pub(crate) fn text(cfg: &Config) -> String {
+    let mut nodes = printer::nodes(cfg.directives());
+    sort_tree(&mut nodes);
+    let mut buf = String::new();
+    printer::write(&mut buf, &nodes, &Layout { indent: 4, margins: false },
+                   0);
+    buf
+}
+
+/// 64-bit FNV-1a hash of the canonical text and its version
+pub(crate) fn hash(text: &str) -> u64 {
+    format!("v{}\n", VERSION).bytes().chain(text.bytes())
+        .fold(FNV_OFFSET, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        })
+}
+
+fn sort_tree(nodes: &mut [Node]) {
+    for node in nodes.iter_mut() {
+        // order of entries matters
+        if node.name == "map" {
+            continue;
+        }
+        if let Some(ref mut children) = node.children {
+            sort_tree(children);
+        }
+    }
+    sort(nodes);
+}
+
+fn sort(nodes: &mut [Node]) {
+    if nodes.iter().all(|node| node.name != "include") {
+        nodes.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+    }
+}
+
+/// Directives having the same key are kept in order
+fn sort_key(node: &Node) -> (&str, String) {
+    match &node.name[..] {
+        "rewrite" | "return" | "set" | "if" | "break" => {
+            ("rewrite", String::new())
+        }
+        "allow" | "deny" => ("allow", String::new()),
+        "location" => match node.args.first().map(|a| &a[..]) {
+            Some("~") | Some("~*") => ("location ~", String::new()),
+            _ => ("location", node.args.join(" ")),
+        },
+        name => (name, String::new()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use {Config, EntryPoint};
+    use includes::Expander;
+    use super::text;
+
+    fn canonical(text_: &str) -> String {
+        text(&Config::parse_str(EntryPoint::Server, text_, "a").unwrap())
+    }
+
+    fn equivalent(a: &str, b: &str) -> bool {
+        let a = Config::parse_str(EntryPoint::Server, a, "a").unwrap();
+        let b = Config::parse_str(EntryPoint::Server, b, "b").unwrap();
+        let result = a.equivalent(&b);
+        assert_eq!(result, a.content_hash() == b.content_hash());
+        result
+    }
+
+    #[test]
+    fn sorted() {
+        assert_eq!(canonical("root /a; index i.html; gzip on;"),
+                   "gzip on;\nindex i.html;\nroot /a;\n");
+        assert!(equivalent("root /a; index i.html;",
+                           "index i.html; root /a;"));
+    }
+
+    #[test]
+    fn quoting() {
+        assert!(equivalent("root \"/a\";", "root '/a';"));
+        assert!(equivalent("location ~ \"^/a\" {}", "location ~ ^/a {}"));
+        assert_eq!(canonical("root '/a b';"), "root \"/a b\";\n");
+    }
+
+    #[test]
+    fn inner_quotes() {
+        let text = "add_header X-A 'say \"hi\"'; set $a \"it's\";\n\
+                    add_header X-B a\\\"b;";
+        assert_eq!(canonical(text),
+                   "add_header X-A 'say \"hi\"';\n\
+                    add_header X-B a\\\"b;\n\
+                    set $a \"it's\";\n");
+        assert!(equivalent(text, "set $a \"it's\"; add_header X-A \
+            'say \"hi\"'; add_header X-B a\\\"b;"));
+        let mut cfg = Config::parse_str(EntryPoint::Server, text, "a")
+            .unwrap();
+        cfg.canonicalize(&Expander::new(Path::new("."))).unwrap();
+        assert_eq!(cfg.canonical_text(), canonical(text));
+    }
+
+    #[test]
+    fn rewrite_order() {
+        assert!(!equivalent("set $a 1; return 200;",
+                            "return 200; set $a 1;"));
+        assert!(!equivalent("rewrite ^/a /b; if ($a) { return 403; }",
+                            "if ($a) { return 403; } rewrite ^/a /b;"));
+        assert!(equivalent("set $a 1; root /a; return 200;",
+                           "root /a; set $a 1; return 200;"));
+    }
+
+    #[test]
+    fn access_order() {
+        assert!(!equivalent("allow 10.0.0.1; deny all;",
+                            "deny all; allow 10.0.0.1;"));
+        assert!(equivalent("allow 10.0.0.1; root /a; deny all;",
+                           "root /a; allow 10.0.0.1; deny all;"));
+    }
+
+    #[test]
+    fn locations() {
+        assert!(!equivalent("location ~ ^/a {} location ~* \\.php$ {}",
+                            "location ~* \\.php$ {} location ~ ^/a {}"));
+        assert!(equivalent("location /b {} location /a {}",
+                           "location /a {} location /b {}"));
+        assert!(equivalent("location /b {} location ~ ^/a {}",
+                           "location ~ ^/a {} location /b {}"));
+    }
+
+    #[test]
+    fn map_entries() {
+        let a = "map $uri $x { default 0; ~^/a 1; ~^/ab 2; }";
+        let b = "map $uri $x { default 0; ~^/ab 2; ~^/a 1; }";
+        assert!(!equivalent(a, b));
+        assert_eq!(canonical(a),
+                   "map $uri $x {\n    default 0;\n    \
+                    ~^/a 1;\n    ~^/ab 2;\n}\n");
+    }
+
+    #[test]
+    fn if_parenthesis() {
+        assert_eq!(canonical("if ($a = 'x') { return 403; }"),
+                   "if ($a = x) {\n    return 403;\n}\n");
+        assert!(equivalent("if ( $a ) { return 403; }",
+                           "if ($a) { return 403; }"));
+    }
+
+    #[test]
+    fn canonicalize() {
+        let mut cfg = Config::parse_str(EntryPoint::Server,
+            "root '/a'; if ($a) { return 403; }", "a").unwrap();
+        let before = cfg.content_hash();
+        cfg.canonicalize(&Expander::new(Path::new("."))).unwrap();
+        assert_eq!(cfg.content_hash(), before);
+        assert_eq!(text(&cfg), cfg.canonical_text());
+    }
+
+    #[test]
+    fn include_keeps_order() {
+        assert!(!equivalent("root /a; include x.conf; index i;",
+                            "index i; include x.conf; root /a;"));
+    }
+}
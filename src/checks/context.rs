@@ -0,0 +1,103 @@
+use std::fmt;
+
+use nginx_config::ast::{Directive, Item};
+use nginx_config::Pos;
+use checks::schema;
+use {Config, EntryPoint};
+
+/// A block directive can be placed in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Context {
+    Main,
+    Http,
+    Server,
+    Location,
+    /// `if` inside the `server` block
+    ServerIf,
+    /// `if` inside the `location` block
+    LocationIf,
+    LimitExcept,
+}
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="{}: `{}` directive is not allowed in {}", _0, _1, _2)]
+    NotAllowed(Pos, String, Context),
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Context::*;
+        f.write_str(match *self {
+            Main => "main context",
+            Http => "http",
+            Server => "server",
+            Location => "location",
+            ServerIf => "if in server",
+            LocationIf => "if in location",
+            LimitExcept => "limit_except",
+        })
+    }
+}
+
+impl From<EntryPoint> for Context {
+    fn from(entry_point: EntryPoint) -> Context {
+        match entry_point {
+            EntryPoint::Main => Context::Main,
+            EntryPoint::Http => Context::Http,
+            EntryPoint::Server => Context::Server,
+            EntryPoint::Location => Context::Location,
+        }
+    }
+}
+
+/// Returns contexts directive is allowed in, as documented by nginx
+///
+/// Returns `None` for directives we don't know.
+pub fn allowed_contexts(name: &str) -> Option<&'static [Context]> {
+    schema::find(name).map(|spec| spec.contexts)
+}
+
+fn child_context(item: &Item, parent: Context) -> Context {
+    match *item {
+        Item::Http(..) => Context::Http,
+        Item::Server(..) => Context::Server,
+        Item::LimitExcept(..) => Context::LimitExcept,
+        Item::If(..) if parent == Context::Server => Context::ServerIf,
+        Item::If(..) => Context::LocationIf,
+        Item::Location(..) => Context::Location,
+        _ => parent,
+    }
+}
+
+fn check_directives(dirs: &[Directive], context: Context,
+    errors: &mut Vec<Error>)
+{
+    for dir in dirs {
+        let name = dir.item.directive_name();
+        if let Some(allowed) = allowed_contexts(name) {
+            if !allowed.contains(&context) {
+                errors.push(Error::NotAllowed(
+                    dir.position, name.to_string(), context));
+            }
+        }
+        if let Some(children) = dir.item.children() {
+            check_directives(children,
+                child_context(&dir.item, context), errors);
+        }
+    }
+}
+
+/// Checks that every directive is placed in a context it's allowed in
+///
+/// Top-level directives are checked against the entry point of the
+/// config, so `listen` in a location snippet or `location` in the main
+/// file are reported.
+pub fn check_context(cfg: &Config) -> Result<(), Vec<Error>> {
+    let mut errors = Vec::new();
+    check_directives(cfg.directives(), cfg.entry_point().into(), &mut errors);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
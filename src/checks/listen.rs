@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::SocketAddr;
+
+use nginx_config::ast::{self, Address, Listen};
+use nginx_config::Pos;
+use {Config};
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="{}: duplicate listen options for {}, \
+                    first ones are at {}", _0, _1, _2)]
+    DuplicateOptions(Pos, String, Pos),
+    #[fail(display="{}: a duplicate default server for {}, \
+                    first one is at {}", _0, _1, _2)]
+    DuplicateDefault(Pos, String, Pos),
+    #[fail(display="{}: listen on {} mixes ssl and non-ssl, \
+                    other one is at {}", _0, _1, _2)]
+    SslMismatch(Pos, String, Pos),
+    #[fail(display="{}: conflicting server name {:?} on {}, \
+                    first one is at {}", _0, _1, _2, _3)]
+    DuplicateServerName(Pos, String, String, Pos),
+    #[fail(display="{}: server listens on {} but not on {}", _0, _1, _2)]
+    MissingFamily(Pos, String, String),
+}
+
+#[derive(Default)]
+struct Listener {
+    options: Option<Pos>,
+    default_server: Option<Pos>,
+    ssl: Option<(Pos, bool)>,
+    names: HashMap<String, Pos>,
+}
+
+/// Normalized `address:port` of the listen directive
+///
+/// `80`, `*:80` and `0.0.0.0:80` are the same address, IPv6 addresses are
+/// formatted as `[::]:80` and unix sockets as `unix:/path`.
+pub fn listen_address(addr: &Address) -> String {
+    match *addr {
+        Address::Ip(sa) => sa.to_string(),
+        Address::StarPort(port) | Address::Port(port) => {
+            SocketAddr::from(([0, 0, 0, 0], port)).to_string()
+        }
+        Address::Unix(ref path) => format!("unix:{}", path.display()),
+    }
+}
+
+/// Returns `true` if listen has options applied to the socket itself
+///
+/// nginx allows such options only once for each `address:port`.
+pub fn has_socket_options(lst: &Listen) -> bool {
+    lst.setfib.is_some() || lst.fastopen.is_some() || lst.backlog.is_some() ||
+    lst.rcvbuf.is_some() || lst.sndbuf.is_some() || lst.deferred ||
+    lst.bind || lst.ipv6only.is_some() || lst.reuseport
+}
+
+fn server_name(name: &ast::ServerName) -> String {
+    use nginx_config::ast::ServerName::*;
+    match *name {
+        // parser keeps quotes in names, strip them to compare `""` names
+        Exact(ref n) => n.trim_matches('"').to_lowercase(),
+        Suffix(ref n) => format!(".{}", n.to_lowercase()),
+        StarSuffix(ref n) => format!("*.{}", n.to_lowercase()),
+        StarPrefix(ref n) => format!("{}.*", n.to_lowercase()),
+        Regex(ref n) => format!("~{}", n),
+    }
+}
+
+/// Checks listen directives of all server blocks for conflicts
+///
+/// Reports things nginx refuses to start with: socket options
+/// (`reuseport`, `backlog`...) specified more than once for the same
+/// address and duplicate `default_server`. Also reports mixing `ssl` and
+/// non-ssl listen on the same address and duplicate server names on the
+/// same address.
+pub fn check_listen(cfg: &Config)
+    -> Result<(), Vec<Error>>
+{
+    use self::Error::*;
+    let mut errors = Vec::new();
+    let mut listeners = HashMap::<String, Listener>::new();
+    for dir in cfg.all_directives() {
+        let server = match dir.item {
+            ast::Item::Server(ref server) => server,
+            _ => continue,
+        };
+        let mut addresses = Vec::new();
+        let mut names = Vec::new();
+        for dir in &server.directives {
+            match dir.item {
+                ast::Item::Listen(ref lst) => {
+                    let addr = listen_address(&lst.address);
+                    if !addresses.contains(&addr) {
+                        addresses.push(addr.clone());
+                    }
+                    let lnr = listeners.entry(addr.clone())
+                        .or_default();
+                    if has_socket_options(lst) {
+                        match lnr.options {
+                            Some(first) => errors.push(DuplicateOptions(
+                                dir.position, addr.clone(), first)),
+                            None => lnr.options = Some(dir.position),
+                        }
+                    }
+                    if lst.default_server {
+                        match lnr.default_server {
+                            Some(first) => errors.push(DuplicateDefault(
+                                dir.position, addr.clone(), first)),
+                            None => lnr.default_server = Some(dir.position),
+                        }
+                    }
+                    match lnr.ssl {
+                        Some((other, ssl)) if ssl != lst.ssl => {
+                            errors.push(SslMismatch(
+                                dir.position, addr.clone(), other));
+                        }
+                        Some(_) => {}
+                        None => lnr.ssl = Some((dir.position, lst.ssl)),
+                    }
+                }
+                ast::Item::ServerName(ref items) => {
+                    for name in items {
+                        let name = server_name(name);
+                        if !names.iter().any(|(_, n)| n == &name) {
+                            names.push((dir.position, name));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if addresses.is_empty() {
+            addresses.push(listen_address(&Address::Port(80)));
+        }
+        if names.is_empty() {
+            names.push((dir.position, String::new()));
+        }
+        for addr in &addresses {
+            let lnr = listeners.entry(addr.clone())
+                .or_default();
+            for &(pos, ref name) in &names {
+                match lnr.names.entry(name.clone()) {
+                    Entry::Occupied(e) => errors.push(DuplicateServerName(
+                        pos, name.clone(), addr.clone(), *e.get())),
+                    Entry::Vacant(e) => {
+                        e.insert(pos);
+                    }
+                }
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
+
+/// Returns port if address is a wildcard address of IPv4 or IPv6 family
+///
+/// The boolean is `true` for IPv6.
+pub fn wildcard_port(addr: &Address) -> Option<(bool, u16)> {
+    match *addr {
+        Address::StarPort(port) | Address::Port(port) => Some((false, port)),
+        Address::Ip(sa) if sa.ip().is_unspecified() => {
+            Some((sa.is_ipv6(), sa.port()))
+        }
+        Address::Ip(..) | Address::Unix(..) => None,
+    }
+}
+
+/// Checks that every server listens on the same ports on IPv4 and IPv6
+///
+/// Only wildcard addresses are compared, i.e. `listen 80` requires
+/// `listen [::]:80` and vice versa.
+pub fn check_ipv6_parity(cfg: &Config)
+    -> Result<(), Vec<Error>>
+{
+    let mut errors = Vec::new();
+    for dir in cfg.all_directives() {
+        let server = match dir.item {
+            ast::Item::Server(ref server) => server,
+            _ => continue,
+        };
+        let ports = server.directives.iter().filter_map(|d| match d.item {
+            ast::Item::Listen(ref lst) => wildcard_port(&lst.address)
+                .map(|port| (d.position, port)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        for &(pos, (ipv6, port)) in &ports {
+            if !ports.iter().any(|&(_, other)| other == (!ipv6, port)) {
+                let v4 = listen_address(&Address::Port(port));
+                let v6 = format!("[::]:{}", port);
+                errors.push(if ipv6 {
+                    Error::MissingFamily(pos, v6, v4)
+                } else {
+                    Error::MissingFamily(pos, v4, v6)
+                });
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
+
+#[cfg(test)]
+mod test {
+    use {Config, EntryPoint};
+    use super::{check_listen, check_ipv6_parity, Error};
+
+    fn check(text: &str) -> Vec<Error> {
+        let cfg = Config::parse_str(EntryPoint::Http, text, "a").unwrap();
+        check_listen(&cfg).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn duplicate_address() {
+        let errs = check("server { listen 10.0.0.1:80 default_server; } \
+                          server { listen 10.0.0.1:80 default_server; }");
+        assert_eq!(errs.len(), 2);
+        assert!(matches!(errs[0], Error::DuplicateDefault(_, ref addr, _)
+                         if addr == "10.0.0.1:80"));
+        // both servers have the same (empty) name
+        assert!(matches!(errs[1], Error::DuplicateServerName(..)));
+        assert!(check("server { listen 80; server_name a; } \
+                       server { listen *:80; server_name b; }").is_empty());
+    }
+
+    #[test]
+    fn same_address() {
+        let errs = check("server { listen 80; server_name a; } \
+                          server { listen 0.0.0.0:80; server_name a; }");
+        assert_eq!(errs.len(), 1);
+        assert!(matches!(errs[0], Error::DuplicateServerName(_, ref name,
+            ref addr, _) if name == "a" && addr == "0.0.0.0:80"));
+        let errs = check("server { listen 443 ssl; server_name a; } \
+                          server { listen 443; server_name b; }");
+        assert!(matches!(errs[..], [Error::SslMismatch(..)]));
+    }
+
+    #[test]
+    fn wildcard_and_specific() {
+        assert!(check("server { listen 80 default_server; } \
+                       server { listen 10.0.0.1:80 default_server; }")
+                .is_empty());
+        assert!(check("server { listen 80 reuseport; server_name a; } \
+                       server { listen 10.0.0.1:80 reuseport; \
+                                server_name a; }")
+                .is_empty());
+    }
+
+    #[test]
+    fn socket_options() {
+        let errs = check("server { listen 80 reuseport; server_name a; } \
+                          server { listen 80 backlog=100; server_name b; }");
+        assert_eq!(errs.len(), 1);
+        assert!(matches!(errs[0], Error::DuplicateOptions(_, ref addr, _)
+                         if addr == "0.0.0.0:80"));
+        assert!(check("server { listen 80 reuseport; server_name a; } \
+                       server { listen 80; server_name b; }").is_empty());
+    }
+
+    fn parity(text: &str) -> Vec<String> {
+        let cfg = Config::parse_str(EntryPoint::Http, text, "a").unwrap();
+        check_ipv6_parity(&cfg).err().unwrap_or_default().iter()
+            .map(|e| match *e {
+                Error::MissingFamily(_, ref has, ref missing) => {
+                    format!("{} {}", has, missing)
+                }
+                ref e => panic!("unexpected error {}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ipv6_parity() {
+        assert_eq!(parity("server { listen 80; listen 443 ssl; \
+                                    listen [::]:443 ssl; }"),
+                   ["0.0.0.0:80 [::]:80"]);
+        assert_eq!(parity("server { listen [::]:80; }"),
+                   ["[::]:80 0.0.0.0:80"]);
+        // every server needs both
+        assert_eq!(parity("server { listen 80; listen [::]:80; } \
+                           server { listen *:80; }"),
+                   ["0.0.0.0:80 [::]:80"]);
+        assert!(parity("server { listen 80; listen [::]:80; \
+                                 listen 10.0.0.1:81; }").is_empty());
+    }
+}
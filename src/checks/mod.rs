@@ -1 +1,6 @@
+pub mod context;
+pub mod listen;
+pub mod policy;
 pub mod proxy_pass;
+pub mod schema;
+pub mod version;
@@ -0,0 +1,314 @@
+use std::path::Path;
+
+use nginx_config::ast::{self, Item, Value};
+use nginx_config::Pos;
+use url::{Url, Host};
+
+use checks::proxy_pass::static_url;
+use includes::AllowList;
+use {Config};
+
+/// Directives denied by the [`Policy::sandbox`](struct.Policy.html)
+///
+/// These allow running arbitrary code or changing the environment of
+/// nginx process.
+pub const SANDBOX_DENIED: &[&str] = &[
+    "perl", "perl_*",
+    "lua_*", "*_by_lua", "*_by_lua_block", "*_by_lua_file",
+    "js_*",
+    "load_module",
+    "env",
+];
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="{}: directive `{}` is not allowed", _0, _1)]
+    Directive(Pos, String),
+    #[fail(display="{}: path {:?} in `{}` is not allowed", _0, _2, _1)]
+    Path(Pos, String, String),
+    #[fail(display="{}: variables in `{}` path {:?} are not allowed",
+           _0, _1, _2)]
+    PathVariable(Pos, String, String),
+    #[fail(display="{}: proxy_pass to {:?} is not allowed", _0, _1)]
+    ProxyHost(Pos, String),
+    #[fail(display="{}: include of {:?} is not allowed", _0, _1)]
+    Include(Pos, String),
+}
+
+/// Restrictions applied to untrusted config snippets
+///
+/// By default nothing is restricted. Paths are the ones used in `root`,
+/// `alias`, `access_log`, `error_log`, `ssl_certificate` and
+/// `ssl_certificate_key`. Logging to `stderr`, `syslog:` and `memory:`
+/// is always allowed.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    denied_directives: Vec<String>,
+    allowed_paths: Option<AllowList>,
+    allowed_includes: Option<AllowList>,
+    allowed_proxy_hosts: Option<Vec<String>>,
+}
+
+impl Policy {
+    pub fn new() -> Policy {
+        Policy::default()
+    }
+    /// Policy which denies [`SANDBOX_DENIED`](constant.SANDBOX_DENIED.html)
+    /// directives, all paths, includes and proxy hosts
+    ///
+    /// Use [`allow_path`](#method.allow_path),
+    /// [`allow_include`](#method.allow_include) and
+    /// [`allow_proxy_host`](#method.allow_proxy_host) to allow some.
+    pub fn sandbox() -> Policy {
+        let mut policy = Policy::new();
+        for name in SANDBOX_DENIED {
+            policy.deny_directive(name);
+        }
+        policy.allowed_paths = Some(AllowList::default());
+        policy.allowed_includes = Some(AllowList::default());
+        policy.allowed_proxy_hosts = Some(Vec::new());
+        policy
+    }
+    /// Deny directive by name, `*` in the name matches any characters
+    pub fn deny_directive(&mut self, pattern: &str) -> &mut Self {
+        self.denied_directives.push(pattern.to_string());
+        self
+    }
+    /// Allow paths starting with this prefix (which may contain globs)
+    ///
+    /// As soon as one prefix is allowed, all other paths are denied.
+    pub fn allow_path(&mut self, prefix: &Path) -> &mut Self {
+        let mut prefixes = self.allowed_paths.take()
+            .map(|list| list.prefixes().to_vec())
+            .unwrap_or_default();
+        prefixes.push(prefix.to_path_buf());
+        self.allowed_paths = Some(AllowList::new(prefixes));
+        self
+    }
+    /// Allow includes with this prefix, see
+    /// [`AllowList`](../../includes/struct.AllowList.html)
+    ///
+    /// As soon as one prefix is allowed, all other includes are denied.
+    /// Includes which are expanded before the check aren't in the config
+    /// anymore, so the policy applies to the included directives.
+    pub fn allow_include(&mut self, prefix: &Path) -> &mut Self {
+        let mut prefixes = self.allowed_includes.take()
+            .map(|list| list.prefixes().to_vec())
+            .unwrap_or_default();
+        prefixes.push(prefix.to_path_buf());
+        self.allowed_includes = Some(AllowList::new(prefixes));
+        self
+    }
+    /// Allow `proxy_pass` to this host, `.example.com` allows subdomains
+    ///
+    /// As soon as one host is allowed, all other hosts are denied.
+    pub fn allow_proxy_host(&mut self, host: &str) -> &mut Self {
+        self.allowed_proxy_hosts.get_or_insert_with(Vec::new)
+            .push(host.to_lowercase());
+        self
+    }
+    fn is_denied(&self, name: &str) -> bool {
+        self.denied_directives.iter().any(|p| wildcard_match(p, name))
+    }
+    fn check_path(&self, pos: Pos, name: &str, value: &Value,
+        errors: &mut Vec<Error>)
+    {
+        let list = match self.allowed_paths {
+            Some(ref list) => list,
+            None => return,
+        };
+        let path = value.to_string();
+        if is_log_target(name, &path) {
+            return;
+        }
+        if path.contains('$') {
+            errors.push(Error::PathVariable(pos, name.to_string(), path));
+        } else if list.check(Path::new(&path), &[], Path::new("")).is_err() {
+            errors.push(Error::Path(pos, name.to_string(), path));
+        }
+    }
+    fn check_include(&self, pos: Pos, value: &Value,
+        errors: &mut Vec<Error>)
+    {
+        let list = match self.allowed_includes {
+            Some(ref list) => list,
+            None => return,
+        };
+        let path = value.to_string();
+        if path.contains('$') ||
+            list.check(Path::new(&path), &[], Path::new("")).is_err()
+        {
+            errors.push(Error::Include(pos, path));
+        }
+    }
+    fn check_proxy(&self, pos: Pos, value: &Value, errors: &mut Vec<Error>) {
+        let hosts = match self.allowed_proxy_hosts {
+            Some(ref hosts) => hosts,
+            None => return,
+        };
+        let text = value.to_string();
+        let url = static_url(&text).map(Url::parse);
+        let allowed = match url {
+            Some(Ok(ref url)) => match url.host() {
+                Some(Host::Domain(name)) => {
+                    let name = name.to_lowercase();
+                    hosts.iter().any(|h| match h.strip_prefix('.') {
+                        Some(parent) => {
+                            name.ends_with(&h[..]) || name == parent
+                        }
+                        None => &name == h,
+                    })
+                }
+                Some(Host::Ipv4(ip)) => hosts.contains(&ip.to_string()),
+                Some(Host::Ipv6(ip)) => {
+                    hosts.contains(&format!("[{}]", ip)) ||
+                    hosts.contains(&ip.to_string())
+                }
+                None => false,
+            },
+            _ => false,
+        };
+        if !allowed {
+            errors.push(Error::ProxyHost(pos, text));
+        }
+    }
+}
+
+/// Log destinations which aren't files
+fn is_log_target(name: &str, path: &str) -> bool {
+    match name {
+        "error_log" => {
+            path == "stderr" || path.starts_with("syslog:") ||
+                path.starts_with("memory:")
+        }
+        "access_log" => path.starts_with("syslog:"),
+        _ => false,
+    }
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(star) => {
+            let (prefix, rest) = (&pattern[..star], &pattern[star+1..]);
+            if !name.starts_with(prefix) {
+                return false;
+            }
+            let name = &name[prefix.len()..];
+            (0..name.len()+1).any(|i| {
+                name.is_char_boundary(i) && wildcard_match(rest, &name[i..])
+            })
+        }
+    }
+}
+
+/// Checks the config against the policy and returns all violations
+pub fn check_policy(cfg: &Config, policy: &Policy)
+    -> Result<(), Vec<Error>>
+{
+    let mut errors = Vec::new();
+    for dir in cfg.all_directives() {
+        let pos = dir.position;
+        let name = dir.item.directive_name();
+        if policy.is_denied(name) {
+            errors.push(Error::Directive(pos, name.to_string()));
+            continue;
+        }
+        match dir.item {
+            Item::Root(ref path) | Item::Alias(ref path) |
+            Item::SslCertificate(ref path) |
+            Item::SslCertificateKey(ref path) |
+            Item::ErrorLog { file: ref path, .. } |
+            Item::AccessLog(ast::AccessLog::On(
+                ast::AccessLogOptions { ref path, .. }))
+            => policy.check_path(pos, name, path, &mut errors),
+            Item::ProxyPass(ref url) => {
+                policy.check_proxy(pos, url, &mut errors);
+            }
+            Item::Include(ref path) => {
+                policy.check_include(pos, path, &mut errors);
+            }
+            _ => {}
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use {Config, EntryPoint};
+    use super::{check_policy, wildcard_match, Error, Policy};
+
+    fn check(policy: &Policy, text: &str) -> Vec<String> {
+        let cfg = Config::parse_str(EntryPoint::Server, text, "a").unwrap();
+        check_policy(&cfg, policy).err().unwrap_or_default().iter()
+            .map(|e| match *e {
+                Error::Directive(_, ref name) => name.clone(),
+                Error::Path(_, _, ref path) => path.clone(),
+                Error::PathVariable(_, _, ref path) => format!("${}", path),
+                Error::ProxyHost(_, ref url) => url.clone(),
+                Error::Include(_, ref path) => path.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_allows_everything() {
+        assert!(check(&Policy::new(), "root /srv; include /etc/*.conf; \
+            proxy_pass http://example.com;").is_empty());
+    }
+
+    #[test]
+    fn directives() {
+        let mut policy = Policy::new();
+        policy.deny_directive("gzip*").deny_directive("root");
+        assert_eq!(check(&policy, "gzip on; gzip_static on; \
+            location / { root /srv; alias /srv/a; }"),
+                   ["gzip", "gzip_static", "root"]);
+        assert!(wildcard_match("*_by_lua", "content_by_lua"));
+        assert!(!wildcard_match("*_by_lua", "content_by_lua_block"));
+        assert!(wildcard_match("lua_*", "lua_"));
+        assert!(!wildcard_match("perl", "perl_set"));
+    }
+
+    #[test]
+    fn paths() {
+        let mut policy = Policy::new();
+        policy.allow_path(Path::new("/srv/www"))
+              .allow_path(Path::new("/var/log/nginx"));
+        assert!(check(&policy, "root /srv/www/a; \
+            access_log /var/log/nginx/a.log; error_log stderr; \
+            access_log syslog:server=unix:/dev/log;").is_empty());
+        assert_eq!(check(&policy, "root /srv/www/../../etc; \
+            alias /srv/wwwx; ssl_certificate /etc/ssl/a.pem; \
+            error_log /var/log/nginx/$host.log;"),
+                   ["/srv/www/../../etc", "/srv/wwwx", "/etc/ssl/a.pem",
+                    "$/var/log/nginx/$host.log"]);
+    }
+
+    #[test]
+    fn sandbox() {
+        let mut policy = Policy::sandbox();
+        assert_eq!(check(&policy, "root /srv; include a.conf; \
+            proxy_pass http://127.0.0.1; content_by_lua_file /a.lua;"),
+                   ["/srv", "a.conf", "http://127.0.0.1",
+                    "content_by_lua_file"]);
+        policy.allow_path(Path::new("/srv"))
+              .allow_include(Path::new("/etc/nginx/conf.d"))
+              .allow_proxy_host(".example.com");
+        assert!(check(&policy, "root /srv; \
+            include /etc/nginx/conf.d/*.conf; \
+            proxy_pass http://example.com; \
+            location /a { proxy_pass http://api.Example.com:8080/; }")
+            .is_empty());
+        assert_eq!(check(&policy, "include /etc/nginx/nginx.conf; \
+            proxy_pass http://badexample.com; proxy_pass http://$host;"),
+                   ["/etc/nginx/nginx.conf", "http://badexample.com",
+                    "http://$host"]);
+    }
+}
@@ -2,18 +2,59 @@ use std::io;
 use std::net::ToSocketAddrs;
 
 use nginx_config::ast;
+use nginx_config::Pos;
 use url::{self, Url, Host};
 use {Config};
 
 #[derive(Fail, Debug)]
+#[non_exhaustive]
 pub enum Error {
-    #[fail(display="Url {:?} is invalid: {}", _0, _1)]
-    InvalidUrl(String, url::ParseError),
-    #[fail(display="Can't resolve {:?} in url {:?}: {}", _0, _1, _2)]
-    Resolve(String, String, io::Error),
-    #[doc(hidden)]
-    #[fail(display="unreachable")]
-    __Nonexhaustive,
+    #[fail(display="{}: Url {:?} is invalid: {}", _0, _1, _2)]
+    InvalidUrl(Pos, String, url::ParseError),
+    #[fail(display="{}: Can't resolve {:?} in url {:?}: {}", _0, _1, _2, _3)]
+    Resolve(Pos, String, String, io::Error),
+}
+
+impl Error {
+    /// Position of the `proxy_pass` directive
+    pub fn position(&self) -> Pos {
+        match *self {
+            Error::InvalidUrl(pos, ..) | Error::Resolve(pos, ..) => pos,
+        }
+    }
+}
+
+/// Variables which may follow the host in `proxy_pass` without a slash
+const URI_VARIABLES: &[&str] = &[
+    "request_uri", "uri", "document_uri", "is_args", "args", "query_string",
+];
+
+/// Returns `proxy_pass` URL up to the first variable
+///
+/// Variables in the path, and URI variables right after the host
+/// (`http://backend$request_uri`) don't change the host, so they are
+/// dropped. Returns `None` if the host itself contains a variable.
+pub(crate) fn static_url(url: &str) -> Option<&str> {
+    let host_start = url.find("://").map(|idx| idx + 3).unwrap_or(0);
+    let host_end = url[host_start..].find('/')
+        .map(|idx| host_start + idx)
+        .unwrap_or(url.len());
+    let var = match url.find('$') {
+        Some(var) => var,
+        None => return Some(url),
+    };
+    if var >= host_end {
+        return Some(&url[..var]);
+    }
+    let name = &url[var+1..];
+    let name_len = name
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(name.len());
+    if var > host_start && URI_VARIABLES.contains(&&name[..name_len]) {
+        Some(&url[..var])
+    } else {
+        None
+    }
 }
 
 pub fn check_hostnames(cfg: &Config)
@@ -27,20 +68,17 @@ pub fn check_selected_hostnames(cfg: &Config,
     -> Result<(), Vec<Error>>
 {
     use self::Error::*;
-    let mut errors = Vec::new();;
+    let mut errors = Vec::new();
     for dir in cfg.all_directives() {
         match dir.item {
             ast::Item::ProxyPass(ref texturl) => {
                 let texturl = texturl.to_string();
-                let url_part = if let Some(off) = texturl.find("$request_uri") {
-                    &texturl[..off]
-                } else {
-                    &texturl
-                };
-                let url = match Url::parse(&url_part) {
+                let url_part = static_url(&texturl).unwrap_or(&texturl);
+                let url = match Url::parse(url_part) {
                     Ok(url) => url,
                     Err(e) => {
-                        errors.push(InvalidUrl(texturl.clone(), e));
+                        errors.push(InvalidUrl(dir.position,
+                                               texturl.clone(), e));
                         continue;
                     }
                 };
@@ -53,8 +91,8 @@ pub fn check_selected_hostnames(cfg: &Config,
                         {
                             Ok(_) => {}
                             Err(e) => {
-                                errors.push(Resolve(val.to_string(),
-                                    texturl.clone(), e));
+                                errors.push(Resolve(dir.position,
+                                    val.to_string(), texturl.clone(), e));
                             }
                         }
                     }
@@ -0,0 +1,343 @@
+use std::fmt;
+
+use nginx_config::ast::{Directive, Item};
+use nginx_config::Pos;
+use args;
+use checks::context::Context::{self, *};
+use {Config};
+
+/// Type of directive argument
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arg {
+    Any,
+    /// `on` or `off`
+    Flag,
+    Number,
+    /// Size like `512`, `10k` or `1m`
+    Size,
+    /// Time interval like `30`, `500ms` or `1h 30m`
+    Time,
+    Enum(&'static [&'static str]),
+}
+
+/// Description of a directive
+///
+/// Types in `args` apply to arguments by position, the last one applies
+/// to all the arguments that follow it.
+#[derive(Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    pub contexts: &'static [Context],
+    pub min_args: usize,
+    pub max_args: Option<usize>,
+    pub args: &'static [Arg],
+}
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="{}: invalid number of arguments in `{}` directive, \
+                    expected {}", _0, _1, _2)]
+    ArgCount(Pos, String, String),
+    #[fail(display="{}: invalid value {:?} in `{}` directive, expected {}",
+           _0, _2, _1, _3)]
+    InvalidArg(Pos, String, String, Arg),
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Arg::*;
+        match *self {
+            Any => f.write_str("any value"),
+            Flag => f.write_str("`on` or `off`"),
+            Number => f.write_str("a number"),
+            Size => f.write_str("a size"),
+            Time => f.write_str("a time interval"),
+            Enum(values) => {
+                write!(f, "one of: {}", values.join(", "))
+            }
+        }
+    }
+}
+
+impl Arg {
+    /// Checks value of the argument, values with variables always match
+    pub fn matches(&self, value: &str) -> bool {
+        use self::Arg::*;
+        if value.contains('$') {
+            return true;
+        }
+        match *self {
+            Any => true,
+            Flag => value == "on" || value == "off",
+            Number => is_number(value),
+            Size => {
+                let num = value.trim_end_matches(&['k', 'K', 'm', 'M',
+                                                   'g', 'G'][..]);
+                value.len() - num.len() <= 1 && is_number(num)
+            }
+            Time => !value.trim().is_empty() &&
+                value.split_whitespace().all(is_time),
+            Enum(values) => values.contains(&value),
+        }
+    }
+}
+
+fn is_number(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_time(mut value: &str) -> bool {
+    const UNITS: &[&str] = &["ms", "s", "m", "h", "d", "w", "M", "y"];
+    while !value.is_empty() {
+        let digits = value.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return false;
+        }
+        value = &value[digits..];
+        if let Some(unit) = UNITS.iter().find(|u| value.starts_with(*u)) {
+            value = &value[unit.len()..];
+        }
+    }
+    true
+}
+
+const fn spec(name: &'static str, contexts: &'static [Context],
+    min_args: usize, max_args: Option<usize>, args: &'static [Arg])
+    -> Spec
+{
+    Spec { name, contexts, min_args, max_args, args }
+}
+
+const ALL: &[Context] = &[Main, Http, Server, Location, ServerIf, LocationIf,
+                          LimitExcept];
+const MAIN: &[Context] = &[Main];
+const HTTP: &[Context] = &[Http];
+const SERVER: &[Context] = &[Server];
+const LOCATION: &[Context] = &[Location];
+const HS: &[Context] = &[Http, Server];
+const SL: &[Context] = &[Server, Location];
+const HSL: &[Context] = &[Http, Server, Location];
+const HSLI: &[Context] = &[Http, Server, Location, LocationIf];
+const HSLL: &[Context] = &[Http, Server, Location, LimitExcept];
+const REWRITE: &[Context] = &[Server, Location, ServerIf, LocationIf];
+
+const ANY: &[Arg] = &[Arg::Any];
+const FLAG: &[Arg] = &[Arg::Flag];
+const TIME: &[Arg] = &[Arg::Time];
+
+/// Directives known to the parser, with contexts as documented by nginx
+pub static SCHEMA: &[Spec] = &[
+    spec("daemon", MAIN, 1, Some(1), FLAG),
+    spec("master_process", MAIN, 1, Some(1), FLAG),
+    spec("worker_processes", MAIN, 1, Some(1), ANY),
+    spec("http", MAIN, 0, Some(0), ANY),
+    spec("server", HTTP, 0, Some(0), ANY),
+    spec("location", SL, 1, Some(2), ANY),
+    spec("limit_except", LOCATION, 1, None, ANY),
+    spec("if", SL, 1, None, ANY),
+    spec("listen", SERVER, 1, None, ANY),
+    spec("server_name", SERVER, 1, None, ANY),
+    spec("proxy_pass", &[Location, LocationIf, LimitExcept],
+         1, Some(1), ANY),
+    spec("proxy_set_header", HSL, 2, Some(2), ANY),
+    spec("proxy_method", HSL, 1, Some(1), ANY),
+    spec("proxy_read_timeout", HSL, 1, Some(1), TIME),
+    spec("proxy_connect_timeout", HSL, 1, Some(1), TIME),
+    spec("proxy_hide_header", HSL, 1, Some(1), ANY),
+    spec("proxy_pass_header", HSL, 1, Some(1), ANY),
+    spec("proxy_pass_request_headers", HSL, 1, Some(1), FLAG),
+    spec("proxy_pass_request_body", HSL, 1, Some(1), FLAG),
+    spec("proxy_http_version", HSL, 1, Some(1),
+         &[Arg::Enum(&["1.0", "1.1"])]),
+    spec("proxy_ignore_headers", HSL, 1, None, &[Arg::Enum(&[
+        "X-Accel-Redirect", "X-Accel-Expires", "X-Accel-Limit-Rate",
+        "X-Accel-Buffering", "X-Accel-Charset", "Expires", "Cache-Control",
+        "Set-Cookie", "Vary",
+    ])]),
+    spec("proxy_intercept_errors", HSL, 1, Some(1), FLAG),
+    spec("proxy_buffering", HSL, 1, Some(1), FLAG),
+    spec("proxy_cache", HSL, 1, Some(1), ANY),
+    spec("proxy_cache_key", HSL, 1, Some(1), ANY),
+    spec("proxy_cache_valid", HSL, 1, None, ANY),
+    spec("proxy_next_upstream_tries", HSL, 1, Some(1), &[Arg::Number]),
+    spec("proxy_next_upstream_timeout", HSL, 1, Some(1), TIME),
+    spec("proxy_next_upstream", HSL, 1, None, &[Arg::Enum(&[
+        "error", "timeout", "invalid_header", "http_500", "http_502",
+        "http_503", "http_504", "http_403", "http_404", "http_429",
+        "non_idempotent", "off",
+    ])]),
+    spec("gzip", HSLI, 1, Some(1), FLAG),
+    spec("gzip_static", HSL, 1, Some(1),
+         &[Arg::Enum(&["on", "off", "always"])]),
+    spec("gzip_proxied", HSL, 1, None, &[Arg::Enum(&[
+        "off", "expired", "no-cache", "no-store", "private",
+        "no_last_modified", "no_etag", "auth", "any",
+    ])]),
+    spec("add_header", HSLI, 2, Some(3),
+         &[Arg::Any, Arg::Any, Arg::Enum(&["always"])]),
+    spec("expires", HSLI, 1, Some(2), ANY),
+    spec("root", HSLI, 1, Some(1), ANY),
+    spec("alias", LOCATION, 1, Some(1), ANY),
+    spec("error_page", HSLI, 2, None, ANY),
+    spec("default_type", HSL, 1, Some(1), ANY),
+    spec("error_log", &[Main, Http, Server, Location], 1, Some(2),
+         &[Arg::Any, Arg::Enum(&[
+            "debug", "info", "notice", "warn", "error", "crit", "alert",
+            "emerg",
+         ])]),
+    spec("rewrite", REWRITE, 2, Some(3), &[Arg::Any, Arg::Any,
+         Arg::Enum(&["last", "break", "redirect", "permanent"])]),
+    spec("return", REWRITE, 1, Some(2), ANY),
+    spec("try_files", SL, 2, None, ANY),
+    spec("set", REWRITE, 2, Some(2), ANY),
+    spec("map", HTTP, 2, Some(2), ANY),
+    spec("client_max_body_size", HSL, 1, Some(1), &[Arg::Size]),
+    spec("include", ALL, 1, Some(1), ANY),
+    spec("empty_gif", LOCATION, 0, Some(0), ANY),
+    spec("internal", LOCATION, 0, Some(0), ANY),
+    spec("etag", HSL, 1, Some(1), FLAG),
+    spec("recursive_error_pages", HSL, 1, Some(1), FLAG),
+    spec("chunked_transfer_encoding", HSL, 1, Some(1), FLAG),
+    spec("keepalive_timeout", HSL, 1, Some(2), TIME),
+    spec("server_tokens", HSL, 1, Some(1),
+         &[Arg::Enum(&["on", "off", "build"])]),
+    spec("ssl_certificate", HS, 1, Some(1), ANY),
+    spec("ssl_certificate_key", HS, 1, Some(1), ANY),
+    spec("rewrite_by_lua_file", HSLI, 1, Some(1), ANY),
+    // only valid in `upstream` which isn't supported by the parser
+    spec("balancer_by_lua_file", &[], 1, Some(1), ANY),
+    spec("access_by_lua_file", HSLI, 1, Some(1), ANY),
+    spec("header_filter_by_lua_file", HSLI, 1, Some(1), ANY),
+    spec("content_by_lua_file", &[Location, LocationIf], 1, Some(1), ANY),
+    spec("body_filter_by_lua_file", HSLI, 1, Some(1), ANY),
+    spec("log_by_lua_file", HSLI, 1, Some(1), ANY),
+    spec("lua_need_request_body", HSLI, 1, Some(1), FLAG),
+    spec("ssl_certificate_by_lua_file", SERVER, 1, Some(1), ANY),
+    spec("ssl_session_fetch_by_lua_file", HTTP, 1, Some(1), ANY),
+    spec("ssl_session_store_by_lua_file", HTTP, 1, Some(1), ANY),
+    spec("allow", HSLL, 1, Some(1), ANY),
+    spec("deny", HSLL, 1, Some(1), ANY),
+    spec("access_log", &[Http, Server, Location, LocationIf, LimitExcept],
+         1, None, ANY),
+    spec("real_ip_header", HSL, 1, Some(1), ANY),
+    spec("real_ip_recursive", HSL, 1, Some(1), FLAG),
+    spec("set_real_ip_from", HSL, 1, Some(1), ANY),
+    spec("index", HSL, 1, None, ANY),
+];
+
+/// Finds description of the directive by name
+pub fn find(name: &str) -> Option<&'static Spec> {
+    SCHEMA.iter().find(|spec| spec.name == name)
+}
+
+fn describe_count(spec: &Spec) -> String {
+    match (spec.min_args, spec.max_args) {
+        (min, Some(max)) if min == max => format!("{}", min),
+        (min, Some(max)) => format!("{} to {}", min, max),
+        (min, None) => format!("at least {}", min),
+    }
+}
+
+fn check_args(dir: &Directive, spec: &Spec, errors: &mut Vec<Error>) {
+    let args = args::args(&dir.item);
+    if args.len() < spec.min_args ||
+        spec.max_args.is_some_and(|max| args.len() > max)
+    {
+        errors.push(Error::ArgCount(dir.position, spec.name.to_string(),
+                                    describe_count(spec)));
+        return;
+    }
+    for (idx, arg) in args.iter().enumerate() {
+        let kind = match spec.args.get(idx).or(spec.args.last()) {
+            Some(kind) => kind,
+            None => break,
+        };
+        if !kind.matches(arg) {
+            errors.push(Error::InvalidArg(dir.position, spec.name.to_string(),
+                                          arg.clone(), *kind));
+        }
+    }
+}
+
+/// Checks number and types of arguments of every directive
+///
+/// Only directives without a block are checked (except `map`, which
+/// is skipped). Contexts are checked by
+/// [`check_context`](../context/fn.check_context.html).
+pub fn check_schema(cfg: &Config) -> Result<(), Vec<Error>> {
+    let mut errors = Vec::new();
+    for dir in cfg.all_directives() {
+        if dir.item.children().is_some() || matches!(dir.item, Item::Map(..)) {
+            continue;
+        }
+        if let Some(spec) = find(dir.item.directive_name()) {
+            check_args(dir, spec, &mut errors);
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
+
+#[cfg(test)]
+mod test {
+    use {Config, EntryPoint};
+    use super::{check_schema, describe_count, find, Arg, Error, SCHEMA};
+
+    fn check(text: &str) -> Vec<String> {
+        let cfg = Config::parse_str(EntryPoint::Server, text, "a").unwrap();
+        check_schema(&cfg).err().unwrap_or_default().iter()
+            .map(|e| match *e {
+                Error::ArgCount(_, ref name, ref count) => {
+                    format!("{} {}", name, count)
+                }
+                Error::InvalidArg(_, ref name, ref arg, _) => {
+                    format!("{} {:?}", name, arg)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arity() {
+        assert!(check("add_header X-A b always; try_files a b c d; \
+                       index a; error_page 404 /404.html;").is_empty());
+        assert_eq!(check("try_files a; index; error_page 404;"),
+                   ["try_files at least 2", "index at least 1",
+                    "error_page at least 2"]);
+        assert_eq!(describe_count(find("internal").unwrap()), "0");
+        assert_eq!(describe_count(find("keepalive_timeout").unwrap()),
+                   "1 to 2");
+    }
+
+    #[test]
+    fn arg_types() {
+        assert!(check("gzip on; client_max_body_size 10m; \
+                       proxy_read_timeout \"1h 30m\"; \
+                       proxy_next_upstream_tries $n;").is_empty());
+        assert_eq!(check("server_tokens x; client_max_body_size 10mb; \
+                          proxy_read_timeout abc; \
+                          proxy_next_upstream_tries x;"),
+                   ["server_tokens \"x\"", "client_max_body_size \"10mb\"",
+                    "proxy_read_timeout \"abc\"",
+                    "proxy_next_upstream_tries \"x\""]);
+        assert!(Arg::Time.matches("500ms"));
+        assert!(!Arg::Time.matches("ms"));
+        assert!(!Arg::Number.matches(""));
+    }
+
+    #[test]
+    fn unknown_directive() {
+        // the parser refuses directives it doesn't know
+        assert!(Config::parse_str(EntryPoint::Server, "gzip_types a;", "a")
+                .is_err());
+        assert!(find("gzip_types").is_none());
+        assert!(find("gzip").is_some());
+        for (idx, spec) in SCHEMA.iter().enumerate() {
+            assert!(SCHEMA[..idx].iter().all(|s| s.name != spec.name),
+                    "duplicate spec for {}", spec.name);
+            assert!(spec.max_args.is_none_or(|max| max >= spec.min_args));
+        }
+    }
+}
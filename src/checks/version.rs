@@ -0,0 +1,245 @@
+use std::fmt;
+use std::str::FromStr;
+
+use nginx_config::ast::{self, Item};
+use nginx_config::Pos;
+use {Config};
+
+/// Version of nginx, e.g. `1.18.0`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+#[derive(Fail, Debug)]
+#[fail(display="invalid nginx version {:?}, expected something like 1.18.0",
+       _0)]
+pub struct VersionError(String);
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="{}: `{}` requires nginx {} or newer", _0, _1, _2)]
+    NotAvailable(Pos, &'static str, Version),
+    #[fail(display="{}: `{}` was removed in nginx {}{}", _0, _1, _2, _3)]
+    Removed(Pos, &'static str, Version, Hint),
+    #[fail(display="{}: `{}` is deprecated since nginx {}{}", _0, _1, _2, _3)]
+    Deprecated(Pos, &'static str, Version, Hint),
+}
+
+/// Suggested replacement, displayed as `, use ... instead`
+#[derive(Debug, Clone, Copy)]
+pub struct Hint(pub Option<&'static str>);
+
+/// A directive or a parameter which is available only in some versions
+#[derive(Debug)]
+pub struct Feature {
+    pub name: &'static str,
+    pub added: Version,
+    pub deprecated: Option<Version>,
+    pub removed: Option<Version>,
+    pub replacement: Option<&'static str>,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+impl FromStr for Version {
+    type Err = VersionError;
+    fn from_str(s: &str) -> Result<Version, VersionError> {
+        let mut parts = s.split('.').map(|p| p.parse::<u32>());
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(a)), Some(Ok(b)), None, None) => Ok(Version(a, b, 0)),
+            (Some(Ok(a)), Some(Ok(b)), Some(Ok(c)), None) => {
+                Ok(Version(a, b, c))
+            }
+            _ => Err(VersionError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(hint) => write!(f, ", use `{}` instead", hint),
+            None => Ok(()),
+        }
+    }
+}
+
+const fn v(major: u32, minor: u32, patch: u32) -> Version {
+    Version(major, minor, patch)
+}
+
+const fn added(name: &'static str, version: Version) -> Feature {
+    Feature {
+        name,
+        added: version,
+        deprecated: None,
+        removed: None,
+        replacement: None,
+    }
+}
+
+/// Features which aren't available in all nginx versions
+///
+/// Everything not listed here is assumed to be available in every version
+/// one could still run.
+pub static FEATURES: &[Feature] = &[
+    Feature {
+        name: "listen ... spdy",
+        added: v(1, 3, 15),
+        deprecated: None,
+        removed: Some(v(1, 9, 5)),
+        replacement: Some("listen ... http2"),
+    },
+    Feature {
+        name: "listen ... http2",
+        added: v(1, 9, 5),
+        deprecated: Some(v(1, 25, 1)),
+        removed: None,
+        replacement: Some("http2 on;"),
+    },
+    added("listen ... ipv6only", v(0, 7, 42)),
+    added("listen ... setfib", v(0, 8, 44)),
+    added("listen ... fastopen", v(1, 5, 8)),
+    added("listen ... proxy_protocol", v(1, 5, 12)),
+    added("listen ... reuseport", v(1, 9, 1)),
+    added("proxy_http_version", v(1, 1, 4)),
+    added("real_ip_recursive", v(1, 3, 0)),
+    added("etag", v(1, 3, 3)),
+    added("gzip_static always", v(1, 3, 6)),
+    added("set_real_ip_from unix:", v(1, 5, 3)),
+    added("access_log ... gzip", v(1, 3, 10)),
+    added("access_log ... if=", v(1, 7, 0)),
+    added("add_header ... always", v(1, 7, 5)),
+    added("proxy_next_upstream_tries", v(1, 7, 5)),
+    added("proxy_next_upstream_timeout", v(1, 7, 5)),
+    added("proxy_ignore_headers Vary", v(1, 7, 7)),
+    added("proxy_next_upstream non_idempotent", v(1, 9, 13)),
+    added("proxy_next_upstream http_403", v(1, 9, 13)),
+    added("proxy_next_upstream http_429", v(1, 11, 13)),
+    added("server_tokens build", v(1, 11, 10)),
+    added("ssl_certificate with variables", v(1, 15, 9)),
+    added("ssl_certificate_key with variables", v(1, 15, 9)),
+];
+
+/// Finds feature in the table by name
+pub fn find(name: &str) -> Option<&'static Feature> {
+    FEATURES.iter().find(|f| f.name == name)
+}
+
+/// Returns names of features from the table used by the directive
+fn features(item: &Item) -> Vec<&'static str> {
+    use nginx_config::ast::ProxyNextUpstreamFlag as P;
+    let mut result = Vec::new();
+    match *item {
+        Item::Listen(ref lst) => {
+            match lst.ext {
+                Some(ast::HttpExt::Spdy) => result.push("listen ... spdy"),
+                Some(ast::HttpExt::Http2) => result.push("listen ... http2"),
+                None => {}
+            }
+            if lst.ipv6only.is_some() {
+                result.push("listen ... ipv6only");
+            }
+            if lst.setfib.is_some() {
+                result.push("listen ... setfib");
+            }
+            if lst.fastopen.is_some() {
+                result.push("listen ... fastopen");
+            }
+            if lst.proxy_protocol {
+                result.push("listen ... proxy_protocol");
+            }
+            if lst.reuseport {
+                result.push("listen ... reuseport");
+            }
+        }
+        Item::ProxyHttpVersion(..) => result.push("proxy_http_version"),
+        Item::RealIpRecursive(..) => result.push("real_ip_recursive"),
+        Item::Etag(..) => result.push("etag"),
+        Item::GzipStatic(ast::GzipStatic::Always) => {
+            result.push("gzip_static always");
+        }
+        Item::SetRealIpFrom(ast::RealIpFrom::Unix) => {
+            result.push("set_real_ip_from unix:");
+        }
+        Item::AccessLog(ast::AccessLog::On(ref opts)) => {
+            if opts.gzip.is_some() {
+                result.push("access_log ... gzip");
+            }
+            if opts.condition.is_some() {
+                result.push("access_log ... if=");
+            }
+        }
+        Item::AddHeader(ref hdr) if hdr.always => {
+            result.push("add_header ... always");
+        }
+        Item::ProxyNextUpstreamTries(..) => {
+            result.push("proxy_next_upstream_tries");
+        }
+        Item::ProxyNextUpstreamTimeout(..) => {
+            result.push("proxy_next_upstream_timeout");
+        }
+        Item::ProxyIgnoreHeaders(ref headers)
+            if headers.iter().any(|h| h == "Vary")
+        => result.push("proxy_ignore_headers Vary"),
+        Item::ProxyNextUpstream(ref flags) => {
+            for flag in flags {
+                match *flag {
+                    P::NonIdempotent => {
+                        result.push("proxy_next_upstream non_idempotent");
+                    }
+                    P::Http403 => result.push("proxy_next_upstream http_403"),
+                    P::Http429 => result.push("proxy_next_upstream http_429"),
+                    _ => {}
+                }
+            }
+        }
+        Item::ServerTokens(ref value) if value.to_string() == "build" => {
+            result.push("server_tokens build");
+        }
+        Item::SslCertificate(ref value) if value.to_string().contains('$')
+        => result.push("ssl_certificate with variables"),
+        Item::SslCertificateKey(ref value)
+            if value.to_string().contains('$')
+        => result.push("ssl_certificate_key with variables"),
+        _ => {}
+    }
+    result
+}
+
+/// Checks that the config can be used with the specified nginx version
+///
+/// Reports directives and parameters which are not available in the
+/// version yet or were removed already, and deprecated ones.
+pub fn check_version(cfg: &Config, version: Version)
+    -> Result<(), Vec<Error>>
+{
+    let mut errors = Vec::new();
+    for dir in cfg.all_directives() {
+        for name in features(&dir.item) {
+            let feature = find(name).expect("feature is in the table");
+            let hint = Hint(feature.replacement);
+            if version < feature.added {
+                errors.push(Error::NotAvailable(
+                    dir.position, feature.name, feature.added));
+            } else if let Some(removed) =
+                feature.removed.filter(|&r| version >= r)
+            {
+                errors.push(Error::Removed(
+                    dir.position, feature.name, removed, hint));
+            } else if let Some(deprecated) =
+                feature.deprecated.filter(|&d| version >= d)
+            {
+                errors.push(Error::Deprecated(
+                    dir.position, feature.name, deprecated, hint));
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
@@ -1,18 +1,24 @@
 use std::io::Read;
 use std::fmt;
 use std::path::{PathBuf, Path};
+use std::slice;
 use std::fs::File;
+use std::str::FromStr;
 
-use errors::{ReadError, ReadEnum};
-use nginx_config;
+use canonical;
+use dump::Dump;
+use errors::{ReadError, ReadEnum, EntryPointError, CanonicalError};
+use includes::{self, Expander, Origins};
+use printer::{self, Layout};
+use recover;
 use nginx_config::visitors::DirectiveIter;
 use nginx_config::ast::{Directive, Main};
 
 
 pub struct Config {
-    #[allow(dead_code)]
     filename: Option<PathBuf>,
     ast: Ast,
+    origins: Origins,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -23,6 +29,30 @@ pub enum EntryPoint {
     Location,
 }
 
+impl FromStr for EntryPoint {
+    type Err = EntryPointError;
+    fn from_str(s: &str) -> Result<EntryPoint, EntryPointError> {
+        match s {
+            "main" => Ok(EntryPoint::Main),
+            "http" => Ok(EntryPoint::Http),
+            "server" => Ok(EntryPoint::Server),
+            "location" => Ok(EntryPoint::Location),
+            _ => Err(EntryPointError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for EntryPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            EntryPoint::Main => "main",
+            EntryPoint::Http => "http",
+            EntryPoint::Server => "server",
+            EntryPoint::Location => "location",
+        })
+    }
+}
+
 enum Ast {
     Main(Main),
     Http(Vec<Directive>),
@@ -30,35 +60,166 @@ enum Ast {
     Location(Vec<Directive>),
 }
 
+impl Ast {
+    fn new(entry_point: EntryPoint, directives: Vec<Directive>) -> Ast {
+        match entry_point {
+            EntryPoint::Main => Ast::Main(Main { directives }),
+            EntryPoint::Http => Ast::Http(directives),
+            EntryPoint::Server => Ast::Server(directives),
+            EntryPoint::Location => Ast::Location(directives),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ReadError> {
+    let mut buf = String::with_capacity(1024);
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| ReadError::new(ReadEnum::Input(e), Some(path)))?;
+    Ok(buf)
+}
+
 impl Config {
     pub fn partial_file(entry_point: EntryPoint, path: &Path)
         -> Result<Config, ReadError>
     {
-        Ok(Config::_partial_file(entry_point, path)?)
+        Config::partial_str(entry_point, &read_file(path)?, path)
     }
 
-    fn _partial_file(entry_point: EntryPoint, path: &Path)
-        -> Result<Config, ReadEnum>
+    /// Parses text of the file when it's already read
+    ///
+    /// Same as [`partial_file`](#method.partial_file), the `path` is used
+    /// in errors and to resolve includes.
+    pub fn partial_str(entry_point: EntryPoint, text: &str, path: &Path)
+        -> Result<Config, ReadError>
     {
-        use self::EntryPoint as E;
-        use self::Ast as A;
-
-        let mut buf = String::with_capacity(1024);
-        let mut f = File::open(path).map_err(ReadEnum::Input)?;
-        f.read_to_string(&mut buf).map_err(ReadEnum::Input)?;
-
-        let ast = match entry_point {
-            E::Main => A::Main(nginx_config::parse_main(&buf)?),
-            E::Http => A::Http(nginx_config::parse_directives(&buf)?),
-            E::Server => A::Server(nginx_config::parse_directives(&buf)?),
-            E::Location => A::Location(nginx_config::parse_directives(&buf)?),
-        };
+        let directives = recover::parse_directives(text)
+            .map_err(|e| ReadError::new(ReadEnum::Syntax(e), Some(path)))?;
         Ok(Config {
             filename: Some(path.to_path_buf()),
-            ast,
+            ast: Ast::new(entry_point, directives),
+            origins: Origins::default(),
         })
     }
 
+    /// Parses config from a string
+    ///
+    /// The `name` is used in errors instead of the file name. Relative
+    /// includes in such config are resolved against the current
+    /// directory.
+    pub fn parse_str(entry_point: EntryPoint, text: &str, name: &str)
+        -> Result<Config, ReadError>
+    {
+        let directives = recover::parse_directives(text)
+            .map_err(|e| ReadError::new(ReadEnum::Syntax(e),
+                                        Some(Path::new(name))))?;
+        Ok(Config {
+            filename: None,
+            ast: Ast::new(entry_point, directives),
+            origins: Origins::default(),
+        })
+    }
+
+    /// Reads and parses config, the `name` is used in errors
+    pub fn from_reader<R: Read>(entry_point: EntryPoint, mut reader: R,
+        name: &str)
+        -> Result<Config, ReadError>
+    {
+        let mut buf = String::with_capacity(1024);
+        reader.read_to_string(&mut buf)
+            .map_err(|e| ReadError::new(ReadEnum::Input(e),
+                                        Some(Path::new(name))))?;
+        Config::parse_str(entry_point, &buf, name)
+    }
+
+    /// Reads config skipping statements which have syntax errors
+    ///
+    /// Returns config with everything that could be parsed and all syntax
+    /// errors found. Fails only if the file can't be read.
+    pub fn partial_file_recovering(entry_point: EntryPoint, path: &Path)
+        -> Result<(Config, Vec<ReadError>), ReadError>
+    {
+        Ok(Config::partial_str_recovering(entry_point, &read_file(path)?,
+                                          path))
+    }
+
+    /// Parses text of the file skipping statements with syntax errors
+    pub fn partial_str_recovering(entry_point: EntryPoint, text: &str,
+        path: &Path)
+        -> (Config, Vec<ReadError>)
+    {
+        let (mut cfg, errors) = Config::_parse_recovering(
+            entry_point, text, path);
+        cfg.filename = Some(path.to_path_buf());
+        (cfg, errors)
+    }
+
+    /// Parses config from a string skipping statements with syntax errors
+    pub fn parse_str_recovering(entry_point: EntryPoint, text: &str,
+        name: &str)
+        -> (Config, Vec<ReadError>)
+    {
+        Config::_parse_recovering(entry_point, text, Path::new(name))
+    }
+
+    fn _parse_recovering(entry_point: EntryPoint, text: &str, name: &Path)
+        -> (Config, Vec<ReadError>)
+    {
+        let (directives, errors) = recover::parse(text,
+            recover::parse_directives);
+        let config = Config {
+            filename: None,
+            ast: Ast::new(entry_point, directives.unwrap_or_default()),
+            origins: Origins::default(),
+        };
+        let errors = errors.into_iter()
+            .map(|e| ReadError::new(ReadEnum::Syntax(e), Some(name)))
+            .collect();
+        (config, errors)
+    }
+
+    /// Reads config from the `nginx -T` dump, expanding includes
+    ///
+    /// Included files are looked up in the dump too.
+    pub fn from_dump(entry_point: EntryPoint, dump: &Dump)
+        -> Result<Config, includes::Error>
+    {
+        let mut cfg = dump.main_config(entry_point)?;
+        dump.expander().expand(&mut cfg)?;
+        Ok(cfg)
+    }
+
+    pub(crate) fn from_directives(entry_point: EntryPoint,
+        filename: Option<&Path>, directives: Vec<Directive>)
+        -> Config
+    {
+        Config {
+            filename: filename.map(|p| p.to_path_buf()),
+            ast: Ast::new(entry_point, directives),
+            origins: Origins::default(),
+        }
+    }
+
+    pub fn entry_point(&self) -> EntryPoint {
+        match self.ast {
+            Ast::Main(..) => EntryPoint::Main,
+            Ast::Http(..) => EntryPoint::Http,
+            Ast::Server(..) => EntryPoint::Server,
+            Ast::Location(..) => EntryPoint::Location,
+        }
+    }
+
+    pub fn filename(&self) -> Option<&Path> {
+        self.filename.as_deref()
+    }
+
+    /// Files directives come from, filled when includes are expanded
+    pub fn origins(&self) -> &Origins {
+        &self.origins
+    }
+    pub(crate) fn set_origins(&mut self, origins: Origins) {
+        self.origins = origins;
+    }
     pub fn directives(&self) -> &[Directive] {
         use self::Ast::*;
         match self.ast {
@@ -83,6 +244,71 @@ impl Config {
             => dirs,
         }
     }
+    /// Formats config using `indent` spaces per nesting level
+    ///
+    /// Unlike `Display`, which always uses four spaces, the text is
+    /// written from the AST, so arguments having quotes are written in a
+    /// way they can be parsed back. Empty lines are placed like `Display`
+    /// does.
+    pub fn to_string_indented(&self, indent: usize) -> String {
+        let layout = Layout { indent, margins: true };
+        let nodes = printer::nodes(self.directives());
+        let mut buf = String::new();
+        if let Ast::Main(_) = self.ast {
+            printer::write(&mut buf, &nodes, &layout, 0);
+            return buf;
+        }
+        // every top-level directive is separated by an empty line
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 {
+                buf.push('\n');
+            }
+            let mut text = String::new();
+            printer::write(&mut text, slice::from_ref(node), &layout, 0);
+            buf.push_str(&text);
+        }
+        buf
+    }
+    /// Brings config to the canonical form
+    ///
+    /// Expands includes, normalizes quoting and sorts directives where
+    /// order doesn't matter. Positions of directives refer to the
+    /// [`canonical_text`](#method.canonical_text) afterwards.
+    pub fn canonicalize(&mut self, expander: &Expander)
+        -> Result<(), CanonicalError>
+    {
+        expander.expand(self).map_err(CanonicalError::Include)?;
+        let text = canonical::text(self);
+        let directives = recover::parse_directives(&text)
+            .map_err(CanonicalError::Reparse)?;
+        self.ast = Ast::new(self.entry_point(), directives);
+        self.origins = Origins::default();
+        Ok(())
+    }
+    /// Returns the canonical form of the config as text
+    ///
+    /// The text is the same for [`equivalent`](#method.equivalent)
+    /// configs.
+    pub fn canonical_text(&self) -> String {
+        canonical::text(self)
+    }
+    /// Checks that configs are the same except formatting, comments,
+    /// quoting and order of directives where order doesn't matter
+    ///
+    /// Includes are compared as written, use
+    /// [`canonicalize`](#method.canonicalize) to expand them first.
+    pub fn equivalent(&self, other: &Config) -> bool {
+        self.entry_point() == other.entry_point() &&
+            canonical::text(self) == canonical::text(other)
+    }
+    /// Returns FNV-1a hash of the canonical text
+    ///
+    /// The hash is the same for [`equivalent`](#method.equivalent)
+    /// configs and doesn't depend on the platform or process. It changes
+    /// only if the canonical form changes in a new version of this crate.
+    pub fn content_hash(&self) -> u64 {
+        canonical::hash(&canonical::text(self))
+    }
 }
 
 impl fmt::Display for Config {
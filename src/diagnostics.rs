@@ -0,0 +1,293 @@
+//! Compiler-style error messages with source snippets
+use std::fmt::{self, Write};
+
+use nginx_config::Pos;
+
+use errors::{ReadError, syntax_error};
+use checks::{context, listen, policy, proxy_pass, schema, version};
+use includes;
+
+
+/// An error message with optional position and hints
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    position: Option<Pos>,
+    message: String,
+    hints: Vec<String>,
+    warning: bool,
+}
+
+/// Errors which can be displayed as a diagnostic
+pub trait ToDiagnostic {
+    fn to_diagnostic(&self) -> Diagnostic;
+}
+
+/// Renders diagnostics for a single source file
+///
+/// Source lines are shown only if the source text is given.
+#[derive(Debug)]
+pub struct Emitter<'a> {
+    name: &'a str,
+    source: Option<&'a str>,
+    color: bool,
+}
+
+impl Diagnostic {
+    pub fn new<M: fmt::Display>(message: M) -> Diagnostic {
+        Diagnostic {
+            position: None,
+            message: message.to_string(),
+            hints: Vec::new(),
+            warning: false,
+        }
+    }
+    /// Creates diagnostic at the position
+    ///
+    /// Leading `line:column: ` is stripped from the message, so display
+    /// of the error types in this crate can be used as is.
+    pub fn at<M: fmt::Display>(position: Pos, message: M) -> Diagnostic {
+        let message = message.to_string();
+        let prefix = format!("{}: ", position);
+        let message = if message.starts_with(&prefix) {
+            message[prefix.len()..].to_string()
+        } else {
+            message
+        };
+        Diagnostic {
+            // zero position is used for directives added by tools
+            position: Some(position).filter(|p| p.line > 0),
+            message,
+            hints: Vec::new(),
+            warning: false,
+        }
+    }
+    pub fn hint<H: fmt::Display>(mut self, hint: H) -> Diagnostic {
+        self.hints.push(hint.to_string());
+        self
+    }
+    /// Marks diagnostic as a warning rather than an error
+    pub fn warning(mut self) -> Diagnostic {
+        self.warning = true;
+        self
+    }
+    pub fn is_warning(&self) -> bool {
+        self.warning
+    }
+    pub fn position(&self) -> Option<Pos> {
+        self.position
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    pub fn hints(&self) -> &[String] {
+        &self.hints
+    }
+}
+
+struct Paint<'a>(bool, &'static str, &'a str);
+
+impl<'a> fmt::Display for Paint<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 {
+            write!(f, "\x1b[{}m{}\x1b[0m", self.1, self.2)
+        } else {
+            f.write_str(self.2)
+        }
+    }
+}
+
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+const BOLD: &str = "1";
+
+impl<'a> Emitter<'a> {
+    pub fn new(name: &'a str, source: Option<&'a str>) -> Emitter<'a> {
+        Emitter { name, source, color: false }
+    }
+    /// Use ANSI colors, should be enabled only when writing to a terminal
+    pub fn color(&mut self, value: bool) -> &mut Self {
+        self.color = value;
+        self
+    }
+    pub fn render(&self, diag: &Diagnostic) -> String {
+        let mut buf = String::new();
+        self.write(&mut buf, diag).expect("writing to string never fails");
+        buf
+    }
+    fn write(&self, out: &mut String, diag: &Diagnostic) -> fmt::Result {
+        let c = self.color;
+        let (color, severity) = if diag.warning {
+            (YELLOW, "warning")
+        } else {
+            (RED, "error")
+        };
+        writeln!(out, "{}{}", Paint(c, color, severity),
+                 Paint(c, BOLD, &format!(": {}", diag.message)))?;
+        let pos = match diag.position {
+            Some(pos) => pos,
+            None => {
+                writeln!(out, " {} {}", Paint(c, BLUE, "-->"), self.name)?;
+                for hint in &diag.hints {
+                    writeln!(out, "  {} hint: {}", Paint(c, BLUE, "="), hint)?;
+                }
+                return Ok(());
+            }
+        };
+        let line = self.source
+            .and_then(|s| s.lines().nth(pos.line - 1));
+        let width = pos.line.to_string().len();
+        let gutter = " ".repeat(width);
+        writeln!(out, "{}{} {}:{}", gutter, Paint(c, BLUE, "-->"),
+                 self.name, pos)?;
+        if let Some(line) = line {
+            let line = line.replace('\t', " ");
+            let start = line.char_indices().nth(pos.column.saturating_sub(1))
+                .map(|(idx, _)| idx)
+                .unwrap_or(line.len());
+            let token = line[start..]
+                .split(|c: char| c.is_whitespace() || c == ';')
+                .next()
+                .map(|t| t.chars().count())
+                .unwrap_or(0);
+            writeln!(out, "{} {}", gutter, Paint(c, BLUE, "|"))?;
+            writeln!(out, "{} {}",
+                     Paint(c, BLUE, &format!("{} |", pos.line)), line)?;
+            writeln!(out, "{} {} {}{}", gutter, Paint(c, BLUE, "|"),
+                     " ".repeat(pos.column.saturating_sub(1)),
+                     Paint(c, color, &"^".repeat(token.max(1))))?;
+        }
+        for hint in &diag.hints {
+            writeln!(out, "{} {} hint: {}", gutter, Paint(c, BLUE, "="),
+                     hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl ToDiagnostic for ReadError {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match self.position() {
+            Some(pos) => Diagnostic::at(pos, self.message()),
+            None => Diagnostic::new(self.message()),
+        }
+    }
+}
+
+impl ToDiagnostic for includes::Error {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match *self {
+            includes::Error::Syntax(_, ref err) => match syntax_error(err) {
+                Some((pos, details)) => {
+                    Diagnostic::at(pos, format!("syntax error: {}", details))
+                }
+                None => Diagnostic::new(self),
+            },
+            includes::Error::Include(_, pos, _) => Diagnostic::at(pos, self),
+            _ => Diagnostic::new(self),
+        }
+    }
+}
+
+impl ToDiagnostic for proxy_pass::Error {
+    fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::at(self.position(), self)
+    }
+}
+
+impl ToDiagnostic for context::Error {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match *self {
+            context::Error::NotAllowed(pos, ref name, _) => {
+                let diag = Diagnostic::at(pos, self);
+                match context::allowed_contexts(name) {
+                    Some(list) if !list.is_empty() => {
+                        let list = list.iter().map(|c| c.to_string())
+                            .collect::<Vec<_>>();
+                        diag.hint(format!("`{}` is allowed in: {}",
+                                          name, list.join(", ")))
+                    }
+                    _ => diag,
+                }
+            }
+        }
+    }
+}
+
+impl ToDiagnostic for schema::Error {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match *self {
+            schema::Error::ArgCount(pos, ..) |
+            schema::Error::InvalidArg(pos, ..)
+            => Diagnostic::at(pos, self),
+        }
+    }
+}
+
+impl ToDiagnostic for version::Error {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match *self {
+            version::Error::NotAvailable(pos, ..) |
+            version::Error::Removed(pos, ..) |
+            version::Error::Deprecated(pos, ..)
+            => Diagnostic::at(pos, self),
+        }
+    }
+}
+
+impl ToDiagnostic for listen::Error {
+    fn to_diagnostic(&self) -> Diagnostic {
+        use checks::listen::Error::*;
+        match *self {
+            DuplicateOptions(pos, ..) => Diagnostic::at(pos, self)
+                .hint("socket options can be specified only once \
+                       for each address"),
+            DuplicateDefault(pos, ..) | SslMismatch(pos, ..) |
+            DuplicateServerName(pos, ..) | MissingFamily(pos, ..)
+            => Diagnostic::at(pos, self),
+        }
+    }
+}
+
+impl ToDiagnostic for policy::Error {
+    fn to_diagnostic(&self) -> Diagnostic {
+        use checks::policy::Error::*;
+        match *self {
+            Directive(pos, ..) | Path(pos, ..) | PathVariable(pos, ..) |
+            ProxyHost(pos, ..) | Include(pos, ..) => Diagnostic::at(pos, self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nginx_config::Pos;
+    use super::{Diagnostic, Emitter};
+
+    #[test]
+    fn snippet() {
+        let diag = Diagnostic::at(Pos { line: 2, column: 5 }, "bad");
+        let text = Emitter::new("a.conf", Some("http {\n    gzip on;\n}"))
+            .render(&diag);
+        assert_eq!(text, "error: bad\n \
+                           --> a.conf:2:5\n  \
+                           |\n\
+                          2 |     gzip on;\n  \
+                           |     ^^^^\n");
+    }
+
+    #[test]
+    fn warning() {
+        let diag = Diagnostic::at(Pos { line: 1, column: 1 }, "old")
+            .warning();
+        let text = Emitter::new("a.conf", Some("gzip on;")).render(&diag);
+        assert!(text.starts_with("warning: old\n"));
+    }
+
+    #[test]
+    fn zero_column() {
+        let diag = Diagnostic::at(Pos { line: 1, column: 0 }, "bad");
+        let text = Emitter::new("a.conf", Some("gzip on;")).render(&diag);
+        assert!(text.ends_with("| ^^^^\n"));
+    }
+}
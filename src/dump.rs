@@ -0,0 +1,238 @@
+//! Reading output of `nginx -T`
+//!
+//! The dump contains every file nginx has loaded, each one prefixed with
+//! a `# configuration file /path/to/file:` line. The first file is the
+//! main one.
+use std::fs::{create_dir_all, write};
+use std::io;
+use std::path::{Path, PathBuf, Component};
+
+use includes::{self, Expander, normalize};
+use recover::{self, parse_directives};
+use {Config, EntryPoint};
+
+
+const MARKER: &str = "# configuration file ";
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="no `{}` lines found, expected output of `nginx -T`",
+           _0)]
+    NoFiles(&'static str),
+    #[fail(display="path {:?} in dump points outside of the directory", _0)]
+    BadPath(PathBuf),
+    #[fail(display="error writing {:?}: {}", _0, _1)]
+    Write(PathBuf, #[fail(cause)] io::Error),
+}
+
+/// Files of the `nginx -T` dump
+#[derive(Debug, Clone)]
+pub struct Dump {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl Dump {
+    /// Splits the dump into files
+    ///
+    /// Anything before the first file (e.g. the `syntax is ok` message)
+    /// is skipped. Empty line nginx puts after each file is removed.
+    pub fn parse(text: &str) -> Result<Dump, Error> {
+        let mut files = Vec::new();
+        let mut current: Option<(PathBuf, String)> = None;
+        for line in text.split_inclusive('\n') {
+            let header = line.trim_end_matches(&['\r', '\n'][..]);
+            if header.starts_with(MARKER) && header.ends_with(':') {
+                files.extend(current.take());
+                let path = &header[MARKER.len()..header.len()-1];
+                current = Some((PathBuf::from(path), String::new()));
+            } else if let Some((_, ref mut data)) = current {
+                data.push_str(line);
+            }
+        }
+        files.extend(current);
+        if files.is_empty() {
+            return Err(Error::NoFiles(MARKER.trim()));
+        }
+        for (_, data) in &mut files {
+            if data.ends_with("\n\n") {
+                data.pop();
+            }
+        }
+        Ok(Dump { files })
+    }
+    /// Path of the main config file
+    pub fn main_file(&self) -> &Path {
+        &self.files[0].0
+    }
+    /// All files in the order they are in the dump
+    pub fn files(&self) -> &[(PathBuf, String)] {
+        &self.files
+    }
+    /// Returns contents of the file
+    ///
+    /// Paths are compared after lexical normalization.
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        let path = normalize(path);
+        self.files.iter()
+            .find(|&(p, _)| normalize(p) == path)
+            .map(|(_, data)| &data[..])
+    }
+    /// Returns expander which reads included files from this dump
+    ///
+    /// Relative includes are resolved against the directory of the main
+    /// file, like nginx does by default.
+    pub fn expander(&self) -> Expander {
+        let mut expander = Expander::new(self.main_file().parent()
+            .unwrap_or_else(|| Path::new("/")));
+        expander.files_from(self);
+        expander
+    }
+    /// Parses main file, includes are not expanded
+    ///
+    /// Syntax errors are reported as `includes::Error::Syntax` so they
+    /// refer to the file in the dump.
+    pub fn main_config(&self, entry_point: EntryPoint)
+        -> Result<Config, includes::Error>
+    {
+        let (path, text) = &self.files[0];
+        let directives = parse_directives(text)
+            .map_err(|e| includes::Error::Syntax(path.clone(), e))?;
+        Ok(Config::from_directives(entry_point, Some(path), directives))
+    }
+    /// Parses main file skipping statements with syntax errors
+    pub fn main_config_recovering(&self, entry_point: EntryPoint)
+        -> (Config, Vec<includes::Error>)
+    {
+        let (path, text) = &self.files[0];
+        let (directives, errors) = recover::parse(text, parse_directives);
+        let config = Config::from_directives(entry_point, Some(path),
+                                             directives.unwrap_or_default());
+        let errors = errors.into_iter()
+            .map(|e| includes::Error::Syntax(path.clone(), e))
+            .collect();
+        (config, errors)
+    }
+    /// Writes all files into the directory
+    ///
+    /// Absolute paths are treated as relative to the directory, i.e.
+    /// `/etc/nginx/nginx.conf` is written to `DIR/etc/nginx/nginx.conf`.
+    pub fn unpack(&self, dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut written = Vec::new();
+        for (path, data) in &self.files {
+            let relative = normalize(path).components()
+                .filter(|c| !matches!(*c,
+                    Component::RootDir | Component::Prefix(..)))
+                .collect::<PathBuf>();
+            let escapes = relative.components()
+                .any(|c| c != Component::CurDir &&
+                         !matches!(c, Component::Normal(..)));
+            if escapes || relative.as_os_str().is_empty() {
+                return Err(Error::BadPath(path.clone()));
+            }
+            let target = dir.join(relative);
+            if let Some(parent) = target.parent() {
+                create_dir_all(parent)
+                    .map_err(|e| Error::Write(parent.to_path_buf(), e))?;
+            }
+            write(&target, data)
+                .map_err(|e| Error::Write(target.clone(), e))?;
+            written.push(target);
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+    use std::fs::{read_to_string, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use EntryPoint;
+    use includes;
+    use super::{Dump, Error};
+
+    #[test]
+    fn parse() {
+        let dump = Dump::parse("\
+            nginx: the configuration file syntax is ok\n\
+            # configuration file /etc/nginx/nginx.conf:\n\
+            include a.conf;\n\
+            \n\
+            # configuration file /etc/nginx/a.conf:\n\
+            gzip on;\n\
+            \n").unwrap();
+        assert_eq!(dump.main_file(), Path::new("/etc/nginx/nginx.conf"));
+        assert_eq!(dump.files().len(), 2);
+        assert_eq!(dump.get(Path::new("/etc/nginx/./a.conf")),
+                   Some("gzip on;\n"));
+        assert_eq!(dump.get(Path::new("/etc/nginx/b.conf")), None);
+    }
+
+    #[test]
+    fn no_header() {
+        assert!(matches!(Dump::parse("http {}\n"), Err(Error::NoFiles(_))));
+        assert!(matches!(Dump::parse(""), Err(Error::NoFiles(_))));
+        // the path must end with a colon
+        assert!(matches!(Dump::parse("# configuration file /a.conf\n"),
+                         Err(Error::NoFiles(_))));
+    }
+
+    #[test]
+    fn truncated() {
+        let dump = Dump::parse("\
+            # configuration file /etc/nginx/nginx.conf:\n\
+            http {\n    server {\n").unwrap();
+        assert_eq!(dump.get(Path::new("/etc/nginx/nginx.conf")),
+                   Some("http {\n    server {\n"));
+        match dump.main_config(EntryPoint::Main) {
+            Err(includes::Error::Syntax(path, _)) => {
+                assert_eq!(path, PathBuf::from("/etc/nginx/nginx.conf"));
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+        let (_, errors) = dump.main_config_recovering(EntryPoint::Main);
+        assert!(!errors.is_empty());
+    }
+
+    #[test]
+    fn duplicate_paths() {
+        let dump = Dump::parse("\
+            # configuration file /etc/nginx/nginx.conf:\n\
+            include a.conf;\n\
+            # configuration file /etc/nginx/a.conf:\n\
+            gzip on;\n\
+            # configuration file /etc/nginx/a.conf:\n\
+            gzip off;\n").unwrap();
+        // files are kept as they are in the dump, the first one is used
+        assert_eq!(dump.files().len(), 3);
+        assert_eq!(dump.get(Path::new("/etc/nginx/a.conf")),
+                   Some("gzip on;\n"));
+    }
+
+    #[test]
+    fn unpack_outside() {
+        let dir = temp_dir().join(format!("nginx-config-mod-dump-{}",
+                                          process::id()));
+        for path in &["../a.conf", "a/../../a.conf", "/"] {
+            let dump = Dump::parse(&format!(
+                "# configuration file {}:\ngzip on;\n", path)).unwrap();
+            match dump.unpack(&dir) {
+                Err(Error::BadPath(bad)) => {
+                    assert_eq!(bad, PathBuf::from(path));
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        assert!(!dir.exists());
+        // absolute paths are relative to the directory
+        let dump = Dump::parse("\
+            # configuration file /etc/nginx/../nginx.conf:\n\
+            gzip on;\n").unwrap();
+        assert_eq!(dump.unpack(&dir).unwrap(), [dir.join("etc/nginx.conf")]);
+        assert_eq!(read_to_string(dir.join("etc/nginx.conf")).unwrap(),
+                   "gzip on;\n");
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,24 +1,153 @@
+use std::fmt;
 use std::io;
-use nginx_config::ParseError;
+use std::path::{Path, PathBuf};
 
+use failure::Fail;
+use nginx_config::{ParseError, Pos};
+
+use includes;
+
+/// Error reading config
+///
+/// Either an I/O error or a syntax error, in the latter case position of
+/// the error is known.
+#[derive(Debug)]
+pub struct ReadError {
+    path: Option<PathBuf>,
+    position: Option<Pos>,
+    error: ReadEnum,
+}
+
+/// Kind of the `ReadError`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Io,
+    Syntax,
+}
+
+/// Error bringing config to the canonical form
 #[derive(Debug, Fail)]
-#[fail(display="{}", _0)]
-pub struct ReadError(ReadEnum);
+pub enum CanonicalError {
+    #[fail(display="{}", _0)]
+    Include(#[fail(cause)] includes::Error),
+    #[fail(display="canonical form can't be parsed back: {}", _0)]
+    Reparse(#[fail(cause)] ParseError),
+}
 
 #[derive(Debug, Fail)]
+#[fail(display="unknown entry point {:?}, \
+                expected one of main, http, server, location", _0)]
+pub struct EntryPointError(pub(crate) String);
+
+#[derive(Debug)]
 pub(crate) enum ReadEnum {
-    #[fail(display="error reading input: {}", _0)]
-    Input(#[fail(cause)] io::Error),
-    #[fail(display="syntax error: {}", _0)]
-    Syntax(#[fail(cause)] ParseError),
+    Input(io::Error),
+    Syntax(ParseError),
 }
 
-impl From<ReadEnum> for ReadError {
-    fn from(x: ReadEnum) -> ReadError {
-        ReadError(x)
+impl ReadError {
+    pub(crate) fn new(error: ReadEnum, path: Option<&Path>) -> ReadError {
+        let position = match error {
+            ReadEnum::Syntax(ref err) => syntax_error(err).map(|(pos, _)| pos),
+            ReadEnum::Input(..) => None,
+        };
+        ReadError {
+            path: path.map(|p| p.to_path_buf()),
+            position,
+            error,
+        }
+    }
+    pub fn kind(&self) -> ErrorKind {
+        match self.error {
+            ReadEnum::Input(..) => ErrorKind::Io,
+            ReadEnum::Syntax(..) => ErrorKind::Syntax,
+        }
+    }
+    /// Path of the file
+    ///
+    /// For configs parsed from a string or a reader this is the `name`
+    /// passed to the parser, which isn't necessarily a file.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+    /// Position of the syntax error
+    pub fn position(&self) -> Option<Pos> {
+        self.position
+    }
+    /// One-based line number of the syntax error
+    pub fn line(&self) -> Option<usize> {
+        self.position.map(|p| p.line)
+    }
+    /// One-based column number of the syntax error
+    pub fn column(&self) -> Option<usize> {
+        self.position.map(|p| p.column)
+    }
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self.error {
+            ReadEnum::Input(ref err) => Some(err),
+            ReadEnum::Syntax(..) => None,
+        }
+    }
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        match self.error {
+            ReadEnum::Syntax(ref err) => Some(err),
+            ReadEnum::Input(..) => None,
+        }
+    }
+    /// Description of the error without file name and position
+    pub fn message(&self) -> String {
+        match self.error {
+            ReadEnum::Input(ref err) => {
+                format!("error reading input: {}", err)
+            }
+            ReadEnum::Syntax(ref err) => match syntax_error(err) {
+                Some((_, details)) => format!("syntax error: {}", details),
+                None => format!("syntax error: {}", err),
+            },
+        }
     }
 }
 
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref path) = self.path {
+            write!(f, "{}:", path.display())?;
+            if let Some(pos) = self.position {
+                write!(f, "{}:", pos)?;
+            }
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.message())
+    }
+}
+
+impl Fail for ReadError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match self.error {
+            ReadEnum::Input(ref err) => Some(err),
+            ReadEnum::Syntax(ref err) => Some(err),
+        }
+    }
+}
+
+/// Extracts position and description from the parse error
+///
+/// `ParseError` is opaque, so we extract them from its display, which
+/// is `Parse error at LINE:COL` followed by the details, one per line.
+pub(crate) fn syntax_error(err: &ParseError) -> Option<(Pos, String)> {
+    let err = err.to_string();
+    let mut lines = err.lines();
+    let pos = lines.next()?.rsplit(" at ").next()?;
+    let mut pos = pos.split(':').map(|x| x.parse::<usize>());
+    let pos = match (pos.next(), pos.next()) {
+        (Some(Ok(line)), Some(Ok(column))) => Pos { line, column },
+        _ => return None,
+    };
+    let details = lines.map(|l| l.trim()).filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    Some((pos, details.join(", ")))
+}
 
 impl From<ParseError> for ReadEnum {
     fn from(x: ParseError) -> ReadEnum {
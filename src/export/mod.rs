@@ -0,0 +1,422 @@
+//! Converting config to and from a JSON or YAML tree
+//!
+//! The tree looks like this (in YAML):
+//!
+//! ```yaml
+//! file: /etc/nginx/nginx.conf
+//! entry_point: main
+//! directives:
+//! - name: listen
+//!   args: ["80", ssl]
+//!   line: 1
+//!   column: 1
+//! - name: location
+//!   args: [/]
+//!   line: 2
+//!   column: 1
+//!   children: []
+//! ```
+//!
+//! The tree is built from the parsed config. Arguments are unquoted,
+//! except when quotes are preserved by the parser (e.g. in regular
+//! expressions), arguments starting and ending with a quote are written
+//! as is. Arguments of `if` don't include parenthesis. Entries of `map`
+//! blocks are represented as children with a key as the name and no
+//! position. If includes are expanded, directives of included files have
+//! the `file` they come from. Position and file are ignored on import,
+//! integer arguments are accepted too.
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de;
+use serde_json;
+use serde_yaml_ng;
+use nginx_config::ast::{Directive, Item};
+
+use args;
+use builder;
+use includes::Origin;
+use {Config, EntryPoint, ReadError};
+
+
+/// Format of the tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+#[derive(Fail, Debug)]
+#[fail(display="unknown format {:?}, expected json or yaml", _0)]
+pub struct FormatError(String);
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="invalid JSON: {}", _0)]
+    Json(#[fail(cause)] serde_json::Error),
+    #[fail(display="invalid YAML: {}", _0)]
+    Yaml(#[fail(cause)] serde_yaml_ng::Error),
+    #[fail(display="invalid config tree: {}", _0)]
+    Tree(String),
+    #[fail(display="can't write argument: {}", _0)]
+    Quote(#[fail(cause)] builder::Error),
+    #[fail(display="resulting config is invalid: {}", _0)]
+    Config(#[fail(cause)] ReadError),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Tree {
+    #[serde(default)]
+    file: Option<String>,
+    entry_point: String,
+    directives: Vec<Node>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    name: String,
+    #[serde(default)]
+    args: Vec<Arg>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    file: Option<String>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    line: Option<usize>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    column: Option<usize>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    children: Option<Vec<Node>>,
+}
+
+/// Argument, integers are read as strings
+#[derive(Serialize)]
+#[serde(transparent)]
+struct Arg(String);
+
+impl FromStr for Format {
+    type Err = FormatError;
+    fn from_str(s: &str) -> Result<Format, FormatError> {
+        match s {
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            _ => Err(FormatError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Format::Json => f.write_str("json"),
+            Format::Yaml => f.write_str("yaml"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Arg {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Arg, D::Error> {
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Arg;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or an integer")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Arg, E> {
+                Ok(Arg(v.to_string()))
+            }
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Arg, E> {
+                Ok(Arg(v.to_string()))
+            }
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Arg, E> {
+                Ok(Arg(v.to_string()))
+            }
+        }
+        d.deserialize_any(Visitor)
+    }
+}
+
+/// Serializes the config
+pub fn export(cfg: &Config, format: Format) -> String {
+    let tree = Tree {
+        file: cfg.filename().map(|path| path.display().to_string()),
+        entry_point: cfg.entry_point().to_string(),
+        directives: nodes(cfg, cfg.directives(), &mut 0),
+    };
+    match format {
+        Format::Json => {
+            let mut text = serde_json::to_string_pretty(&tree)
+                .expect("tree is always serializable");
+            text.push('\n');
+            text
+        }
+        Format::Yaml => serde_yaml_ng::to_string(&tree)
+            .expect("tree is always serializable"),
+    }
+}
+
+/// Reads the serialized config
+///
+/// The `name` is used in errors of the resulting config.
+pub fn import(text: &str, format: Format, name: &str)
+    -> Result<Config, Error>
+{
+    let tree: Tree = match format {
+        Format::Json => serde_json::from_str(text).map_err(Error::Json)?,
+        Format::Yaml => serde_yaml_ng::from_str(text).map_err(Error::Yaml)?,
+    };
+    let entry_point = tree.entry_point.parse::<EntryPoint>()
+        .map_err(|e| Error::Tree(e.to_string()))?;
+    let mut buf = String::new();
+    for dir in &tree.directives {
+        write_directive(&mut buf, dir, 0)?;
+    }
+    Config::parse_str(entry_point, &buf, name).map_err(Error::Config)
+}
+
+/// Builds nodes, `index` is the index of the first directive in the
+/// depth-first order
+fn nodes(cfg: &Config, dirs: &[Directive], index: &mut usize) -> Vec<Node> {
+    dirs.iter().map(|dir| {
+        let file = match cfg.origins().get(*index) {
+            Origin::Included(path, _) => Some(path.display().to_string()),
+            Origin::Main | Origin::Unknown => None,
+        };
+        *index += 1;
+        let children = match dir.item {
+            Item::Map(ref map) => Some(args::map_entries(map).into_iter()
+                .map(|mut entry| {
+                    let name = entry.remove(0);
+                    Node {
+                        name,
+                        args: entry.into_iter().map(Arg).collect(),
+                        file: None,
+                        line: None,
+                        column: None,
+                        children: None,
+                    }
+                })
+                .collect()),
+            _ => dir.item.children().map(|dirs| nodes(cfg, dirs, index)),
+        };
+        Node {
+            name: dir.item.directive_name().to_string(),
+            args: args::args(&dir.item).into_iter().map(Arg).collect(),
+            file,
+            line: Some(dir.position.line),
+            column: Some(dir.position.column),
+            children,
+        }
+    }).collect()
+}
+
+fn write_directive(buf: &mut String, dir: &Node, indent: usize)
+    -> Result<(), Error>
+{
+    if dir.name.is_empty() {
+        return Err(Error::Tree("directive must have a name".into()));
+    }
+    // names are quoted only in `map` blocks
+    let name = quote(&dir.name)?;
+    let args = dir.args.iter().map(|arg| quote(&arg.0))
+        .collect::<Result<Vec<_>, _>>()?;
+    for _ in 0..indent {
+        buf.push_str("    ");
+    }
+    buf.push_str(&name);
+    if name == "if" {
+        buf.push_str(" (");
+        buf.push_str(&args.join(" "));
+        buf.push(')');
+    } else {
+        for arg in &args {
+            buf.push(' ');
+            buf.push_str(arg);
+        }
+    }
+    match dir.children {
+        Some(ref children) => {
+            buf.push_str(" {\n");
+            for child in children {
+                write_directive(buf, child, indent + 1)?;
+            }
+            for _ in 0..indent {
+                buf.push_str("    ");
+            }
+            buf.push_str("}\n");
+        }
+        None => buf.push_str(";\n"),
+    }
+    Ok(())
+}
+
+pub(crate) fn is_quoted(word: &str) -> bool {
+    word.len() >= 2 &&
+        (word.starts_with('"') && word.ends_with('"') ||
+         word.starts_with('\'') && word.ends_with('\''))
+}
+
+fn quote(arg: &str) -> Result<String, Error> {
+    if is_quoted(arg) {
+        return Ok(arg.to_string());
+    }
+    builder::quote(arg).map_err(Error::Quote)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{self, Value};
+    use serde_yaml_ng;
+    use dump::Dump;
+    use {Config, EntryPoint};
+    use super::{export, import, Format, Error};
+
+    const CONFIG: &str = "\
+        listen unix:/var/run/nginx.sock;\n\
+        listen [::]:443 ssl http2;\n\
+        server_name example.com *.example.org;\n\
+        root \"/var/www/my site\";\n\
+        location ~* \"\\.(gif|jpg)$\" {\n\
+            if ($http_x = \"a b\") { return 403; }\n\
+            if (!-f $request_filename) { rewrite ^/(.*)$ /index.php last; }\n\
+        }\n\
+        location = / { try_files $uri @back; }\n\
+        location @back { proxy_pass http://127.0.0.1:8080; }\n\
+        map $uri $x {\n\
+            hostnames;\n\
+            default 0;\n\
+            ~^/a 1;\n\
+            \\default 2;\n\
+            .example.com \"\";\n\
+        }\n\
+        access_log /var/log/a.log main buffer=32k if=$log;\n\
+    ";
+
+    /// Parses the tree dropping positions, as they change on import
+    fn parse(text: &str, format: Format) -> Value {
+        fn strip(value: &mut Value) {
+            match *value {
+                Value::Object(ref mut map) => {
+                    map.remove("line");
+                    map.remove("column");
+                    map.remove("file");
+                    map.values_mut().for_each(strip);
+                }
+                Value::Array(ref mut items) => {
+                    items.iter_mut().for_each(strip);
+                }
+                _ => {}
+            }
+        }
+        let mut value = match format {
+            Format::Json => serde_json::from_str(text).unwrap(),
+            Format::Yaml => serde_yaml_ng::from_str(text).unwrap(),
+        };
+        strip(&mut value);
+        value
+    }
+
+    fn round_trip(format: Format) {
+        let cfg = Config::parse_str(EntryPoint::Http, CONFIG, "a").unwrap();
+        let tree = export(&cfg, format);
+        let copy = import(&tree, format, "b").unwrap();
+        assert_eq!(copy.to_string(), cfg.to_string());
+        assert_eq!(parse(&export(&copy, format), format),
+                   parse(&tree, format));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(Format::Json);
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        round_trip(Format::Yaml);
+    }
+
+    #[test]
+    fn quotes() {
+        let text = "\
+            add_header X-A 'say \"hi\"';\n\
+            add_header X-B \"it's\";\n\
+            add_header X-C a\\\"b;\n\
+            set $a \"'b'\";\n\
+        ";
+        let cfg = Config::parse_str(EntryPoint::Server, text, "a").unwrap();
+        for &format in &[Format::Json, Format::Yaml] {
+            let tree = export(&cfg, format);
+            let copy = import(&tree, format, "b").unwrap();
+            assert_eq!(copy.canonical_text(), cfg.canonical_text());
+            assert_eq!(parse(&export(&copy, format), format),
+                       parse(&tree, format));
+        }
+        let tree = parse(&export(&cfg, Format::Json), Format::Json);
+        assert_eq!(tree["directives"][0]["args"][1], "say \"hi\"");
+        assert_eq!(tree["directives"][1]["args"][1], "it's");
+    }
+
+    #[test]
+    fn unquotable() {
+        match import("{\"entry_point\": \"server\", \"directives\": \
+                      [{\"name\": \"root\", \"args\": [\"'a' \\\"b\\\"\"]}]}",
+                     Format::Json, "a")
+        {
+            Err(Error::Quote(..)) => {}
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("argument is written"),
+        }
+    }
+
+    #[test]
+    fn files() {
+        let dump = Dump::parse("\
+            # configuration file /etc/nginx/nginx.conf:\n\
+            server {\n\
+            \x20   include /etc/nginx/a.conf;\n\
+            }\n\
+            # configuration file /etc/nginx/a.conf:\n\
+            location / { root /var/www; }\n\
+        ").unwrap();
+        let mut cfg = dump.main_config(EntryPoint::Http).unwrap();
+        dump.expander().expand(&mut cfg).unwrap();
+        let tree: Value = serde_json::from_str(
+            &export(&cfg, Format::Json)).unwrap();
+        let server = &tree["directives"][0];
+        assert_eq!(tree["file"], "/etc/nginx/nginx.conf");
+        assert!(server.get("file").is_none());
+        let location = &server["children"][0];
+        assert_eq!(location["file"], "/etc/nginx/a.conf");
+        assert_eq!(location["children"][0]["file"], "/etc/nginx/a.conf");
+    }
+
+    #[test]
+    fn import_yaml() {
+        let cfg = import("\
+            entry_point: server\n\
+            directives:\n\
+            - name: listen\n  args: [80, default_server]\n\
+            - name: gzip\n  args: [on]\n\
+            - name: location\n  args: [/]\n  children:\n\
+            \x20 - name: root\n    args: [/var/www]\n\
+        ", Format::Yaml, "a").unwrap();
+        assert_eq!(cfg.to_string(), "\
+            listen 80 default_server;\n\n\
+            gzip on;\n\n\
+            location / {\n\
+            \x20   root /var/www;\n\
+            }\n");
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let text = format!(
+            "{{\"entry_point\": \"main\", \"directives\": {}{}}}",
+            "[{\"name\": \"http\", \"children\": ".repeat(10000),
+            "[]}]".repeat(10000));
+        match import(&text, Format::Json, "a") {
+            Err(Error::Json(..)) => {}
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("deep nesting is accepted"),
+        }
+    }
+}
@@ -0,0 +1,921 @@
+//! Expanding `include` directives
+use std::fs::{read_dir, read_to_string, canonicalize};
+use std::fs::{read_link, symlink_metadata};
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf, Component};
+
+use nginx_config::ast::{Directive, Item};
+use nginx_config::{ParseError, Pos};
+use dump::Dump;
+use recover::{self, parse_directives};
+use {Config};
+
+
+/// Limit of symlinks followed when resolving a single path, as in Linux
+const MAX_SYMLINKS: usize = 40;
+
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display="error reading {:?}: {}", _0, _1)]
+    Read(PathBuf, #[fail(cause)] io::Error),
+    #[fail(display="syntax error in {:?}: {}", _0, _1)]
+    Syntax(PathBuf, #[fail(cause)] ParseError),
+    #[fail(display="include cycle: {}", _0)]
+    Cycle(String),
+    #[fail(display="include path {:?} is not allowed", _0)]
+    NotAllowed(PathBuf),
+    #[fail(display="include path {:?} resolves to {:?} which is not allowed",
+           _0, _1)]
+    ResolvesOutside(PathBuf, PathBuf),
+    /// Error of the include directive at the position of the file, the
+    /// file is `None` for the main one
+    #[fail(display="{}: {}", _1, _2)]
+    Include(Option<PathBuf>, Pos, Box<Error>),
+}
+
+/// A list of path prefixes include directives are allowed to point to
+///
+/// Paths are compared after lexical normalization, so `a/../../b` can't
+/// escape the prefix. Prefixes may contain glob patterns, and glob
+/// patterns in includes are allowed only if the prefix has the same
+/// pattern or `*` at that place.
+#[derive(Debug, Clone, Default)]
+pub struct AllowList {
+    prefixes: Vec<PathBuf>,
+    canonicalize: bool,
+}
+
+/// Replaces include directives with the contents of included files
+///
+/// Relative paths are resolved against the prefix, both in the main file
+/// and in the included ones, the same way nginx resolves them against its
+/// configuration prefix. Glob patterns (`*`, `?`, `[...]`) are expanded
+/// in sorted order. Absolute includes are kept as is unless
+/// [`include_root`](#method.include_root) is set.
+#[derive(Debug, Clone)]
+pub struct Expander {
+    prefix: PathBuf,
+    include_root: Option<PathBuf>,
+    allow_list: Option<AllowList>,
+    dump: Option<Dump>,
+}
+
+/// Files directives of the expanded config come from
+///
+/// The file of every directive is recorded during expansion, in the
+/// depth-first order of directives. Indexes refer to the config right
+/// after expansion, adding or removing directives shifts them.
+#[derive(Debug, Clone, Default)]
+pub struct Origins {
+    files: Vec<IncludedFile>,
+    /// Position and index of the file of each directive, `None` is
+    /// the main file
+    directives: Vec<(Pos, Option<usize>)>,
+}
+
+#[derive(Debug, Clone)]
+struct IncludedFile {
+    path: PathBuf,
+    text: String,
+}
+
+/// File a directive comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin<'a> {
+    /// The main file, also returned if there were no includes
+    Main,
+    /// Path and text of the included file
+    Included(&'a Path, &'a str),
+    /// Directives of several files have the same position
+    Unknown,
+}
+
+/// File directives being expanded are read from
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    /// The config itself, files are taken from its origins
+    Config,
+    /// Index of the included file
+    File(usize),
+}
+
+/// State of a single expansion
+struct Expansion<'a> {
+    stack: Vec<PathBuf>,
+    origins: Origins,
+    /// Files of directives before expansion, empty if there were no
+    /// includes expanded
+    previous: Vec<Option<usize>>,
+    next: usize,
+    syntax_errors: Option<&'a mut Vec<Error>>,
+}
+
+impl Origins {
+    /// Returns the file of the directive by its index in the depth-first
+    /// order, as in `Config::all_directives`
+    pub fn get(&self, index: usize) -> Origin<'_> {
+        match self.directives.get(index) {
+            Some(&(_, file)) => self.origin(file),
+            None if self.files.is_empty() => Origin::Main,
+            None => Origin::Unknown,
+        }
+    }
+    /// Returns the file the directive at the position comes from
+    ///
+    /// Errors of checks have only a position, so if directives of several
+    /// files have the same position, the file is unknown.
+    pub fn find(&self, pos: Pos) -> Origin<'_> {
+        let mut files = self.directives.iter()
+            .filter(|&&(dpos, _)| dpos == pos)
+            .map(|&(_, file)| file);
+        match files.next() {
+            Some(file) if files.all(|other| other == file) => {
+                self.origin(file)
+            }
+            Some(_) => Origin::Unknown,
+            None => Origin::Main,
+        }
+    }
+    fn origin(&self, file: Option<usize>) -> Origin<'_> {
+        match file {
+            Some(idx) => {
+                let file = &self.files[idx];
+                Origin::Included(&file.path, &file.text)
+            }
+            None => Origin::Main,
+        }
+    }
+    fn path(&self, file: Option<usize>) -> Option<PathBuf> {
+        file.map(|idx| self.files[idx].path.clone())
+    }
+    /// Adds the file if it's not added yet, returns its index
+    fn add(&mut self, path: &Path, text: String) -> usize {
+        match self.files.iter().position(|file| file.path == path) {
+            Some(idx) => idx,
+            None => {
+                self.files.push(IncludedFile {
+                    path: path.to_path_buf(),
+                    text,
+                });
+                self.files.len() - 1
+            }
+        }
+    }
+}
+
+impl<'a> Expansion<'a> {
+    fn new(cfg: &Config, syntax_errors: Option<&'a mut Vec<Error>>)
+        -> Expansion<'a>
+    {
+        let origins = cfg.origins();
+        Expansion {
+            stack: Vec::new(),
+            origins: Origins {
+                files: origins.files.clone(),
+                directives: Vec::new(),
+            },
+            previous: origins.directives.iter()
+                .map(|&(_, file)| file)
+                .collect(),
+            next: 0,
+            syntax_errors,
+        }
+    }
+    /// Returns the file of the next directive of the source
+    fn file(&mut self, source: Source) -> Option<usize> {
+        match source {
+            Source::Config => {
+                let file = self.previous.get(self.next).cloned()
+                    .unwrap_or(None);
+                self.next += 1;
+                file
+            }
+            Source::File(idx) => Some(idx),
+        }
+    }
+}
+
+impl AllowList {
+    pub fn new<I, P>(prefixes: I) -> AllowList
+        where I: IntoIterator<Item=P>,
+              P: AsRef<Path>,
+    {
+        AllowList {
+            prefixes: prefixes.into_iter()
+                .map(|p| normalize(p.as_ref()))
+                .collect(),
+            canonicalize: false,
+        }
+    }
+    pub fn prefixes(&self) -> &[PathBuf] {
+        &self.prefixes
+    }
+    /// Also resolve symlinks in existing included files and prefixes
+    ///
+    /// This is needed if a symlink in an allowed directory may point
+    /// outside of it.
+    pub fn canonicalize(&mut self, value: bool) -> &mut Self {
+        self.canonicalize = value;
+        self
+    }
+    /// Checks path as written in include directive and files it resolves to
+    ///
+    /// Relative prefixes are resolved against `base` for comparing
+    /// against canonical file paths.
+    pub fn check(&self, include: &Path, files: &[PathBuf], base: &Path)
+        -> Result<(), Error>
+    {
+        self.check_in(include, files, base, None)
+    }
+    /// Checks include, files under the `root` are resolved inside it
+    ///
+    /// Absolute symlinks there point into the root, as if it was
+    /// a filesystem root, and are compared against absolute prefixes.
+    fn check_in(&self, include: &Path, files: &[PathBuf], base: &Path,
+        root: Option<&Path>)
+        -> Result<(), Error>
+    {
+        let include = normalize(include);
+        if !self.prefixes.iter().any(|p| prefix_matches(p, &include)) {
+            return Err(Error::NotAllowed(include));
+        }
+        if self.canonicalize {
+            let prefixes = self.prefixes.iter()
+                .map(|p| normalize(&file_id(&base.join(p))))
+                .collect::<Vec<_>>();
+            let root_prefixes = root.map(|root| {
+                self.prefixes.iter()
+                    .filter(|p| p.is_absolute())
+                    .map(|p| canonicalize_in(root, p)
+                        .unwrap_or_else(|_| p.clone()))
+                    .collect::<Vec<_>>()
+            });
+            for file in files {
+                let rooted = root.and_then(|root| {
+                    file.strip_prefix(root).ok().map(|rel| (root, rel))
+                });
+                let (real, prefixes) = match rooted {
+                    Some((root, rel)) => (
+                        canonicalize_in(root, &Path::new("/").join(rel)),
+                        root_prefixes.as_ref()
+                            .expect("root prefixes exist with root"),
+                    ),
+                    None => (canonicalize(file), &prefixes),
+                };
+                let real = match real {
+                    Ok(real) => real,
+                    Err(_) => continue,  // reading will fail anyway
+                };
+                if !prefixes.iter().any(|p| prefix_matches(p, &real)) {
+                    return Err(Error::ResolvesOutside(include, real));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Expander {
+    pub fn new(prefix: &Path) -> Expander {
+        Expander {
+            prefix: prefix.to_path_buf(),
+            include_root: None,
+            allow_list: None,
+            dump: None,
+        }
+    }
+    /// Read included files from the `nginx -T` dump instead of filesystem
+    ///
+    /// Absolute includes are looked up in the dump as is.
+    pub fn files_from(&mut self, dump: &Dump) -> &mut Self {
+        self.dump = Some(dump.clone());
+        self
+    }
+    /// Expand absolute includes too, looking them up in this directory
+    ///
+    /// The directory is treated as a filesystem root, i.e. with the root
+    /// of `/srv/chroot`, `include /etc/nginx/mime.types` reads
+    /// `/srv/chroot/etc/nginx/mime.types`.
+    pub fn include_root(&mut self, path: &Path) -> &mut Self {
+        self.include_root = Some(path.to_path_buf());
+        self
+    }
+    /// Check every include against the list, at every depth of expansion
+    pub fn allow_list(&mut self, list: &AllowList) -> &mut Self {
+        self.allow_list = Some(list.clone());
+        self
+    }
+    /// Checks includes left in the config against the allow list
+    ///
+    /// Unlike [`allow_list`](#method.allow_list), the empty list here
+    /// means no includes are allowed.
+    pub fn check(&self, cfg: &Config, list: &AllowList) -> Result<(), Error> {
+        for (idx, dir) in cfg.all_directives().enumerate() {
+            if let Item::Include(ref path) = dir.item {
+                let path = PathBuf::from(path.to_string());
+                let files = match self.resolve(&path) {
+                    Some(pattern) => self.glob(&pattern),
+                    None => self.glob(&path),
+                };
+                files.and_then(|files| {
+                    list.check_in(&path, &files, &self.prefix,
+                                  self.include_root.as_deref())
+                }).map_err(|e| {
+                    let file = match cfg.origins().get(idx) {
+                        Origin::Included(path, _) => Some(path.into()),
+                        Origin::Main | Origin::Unknown => None,
+                    };
+                    Error::Include(file, dir.position, Box::new(e))
+                })?;
+            }
+        }
+        Ok(())
+    }
+    /// Expands includes, recording the [`Origins`](struct.Origins.html)
+    /// of directives in the config
+    pub fn expand(&self, cfg: &mut Config) -> Result<(), Error> {
+        let mut state = Expansion::new(cfg, None);
+        self.expand_config(cfg, &mut state)
+    }
+    /// Expands includes skipping statements which have syntax errors
+    ///
+    /// Returns syntax errors of all included files, other errors are
+    /// returned as errors.
+    pub fn expand_recovering(&self, cfg: &mut Config)
+        -> Result<Vec<Error>, Error>
+    {
+        let mut errors = Vec::new();
+        {
+            let mut state = Expansion::new(cfg, Some(&mut errors));
+            self.expand_config(cfg, &mut state)?;
+        }
+        Ok(errors)
+    }
+    fn expand_config(&self, cfg: &mut Config, state: &mut Expansion)
+        -> Result<(), Error>
+    {
+        if let Some(path) = cfg.filename() {
+            state.stack.push(self.file_id(path));
+        }
+        self.expand_directives(cfg.directives_mut(), Source::Config, state)?;
+        cfg.set_origins(mem::take(&mut state.origins));
+        Ok(())
+    }
+    fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        if path.is_absolute() && self.dump.is_some() {
+            Some(path.to_path_buf())
+        } else if path.is_absolute() {
+            self.include_root.as_ref().map(|root| {
+                root.join(path.components()
+                    .filter(|c| !matches!(*c, Component::RootDir))
+                    .collect::<PathBuf>())
+            })
+        } else {
+            Some(self.prefix.join(path))
+        }
+    }
+    fn expand_directives(&self, dirs: &mut Vec<Directive>, source: Source,
+        state: &mut Expansion)
+        -> Result<(), Error>
+    {
+        let source_dirs = mem::take(dirs);
+        for mut dir in source_dirs {
+            let file = state.file(source);
+            let pattern = match dir.item {
+                Item::Include(ref path) => {
+                    let path = PathBuf::from(path.to_string());
+                    self.resolve(&path).map(|pattern| (path, pattern))
+                }
+                _ => None,
+            };
+            let (path, pattern) = match pattern {
+                Some(pair) => pair,
+                None => {
+                    state.origins.directives.push((dir.position, file));
+                    if let Some(children) = dir.item.children_mut() {
+                        self.expand_directives(children, source, state)?;
+                    }
+                    dirs.push(dir);
+                    continue;
+                }
+            };
+            let including = state.origins.path(file);
+            let position = dir.position;
+            let at = |e| {
+                Error::Include(including.clone(), position, Box::new(e))
+            };
+            let files = self.glob(&pattern).map_err(&at)?;
+            if let Some(ref list) = self.allow_list {
+                list.check_in(&path, &files, &self.prefix,
+                              self.include_root.as_deref())
+                    .map_err(&at)?;
+            }
+            for path in files {
+                let id = self.file_id(&path);
+                if state.stack.contains(&id) {
+                    let chain = state.stack.iter().chain(Some(&id))
+                        .map(|p| p.display().to_string())
+                        .collect::<Vec<_>>();
+                    return Err(at(Error::Cycle(chain.join(" -> "))));
+                }
+                let text = self.read(&path).map_err(&at)?;
+                let mut included = match state.syntax_errors {
+                    Some(ref mut errors) => {
+                        let (dirs, errs) = recover::parse(&text,
+                                                          parse_directives);
+                        errors.extend(errs.into_iter()
+                            .map(|e| Error::Syntax(path.clone(), e)));
+                        dirs.unwrap_or_default()
+                    }
+                    None => parse_directives(&text)
+                        .map_err(|e| Error::Syntax(path.clone(), e))?,
+                };
+                let idx = state.origins.add(&path, text);
+                state.stack.push(id);
+                self.expand_directives(&mut included, Source::File(idx),
+                                       state)?;
+                state.stack.pop();
+                dirs.extend(included);
+            }
+        }
+        Ok(())
+    }
+    fn glob(&self, pattern: &Path) -> Result<Vec<PathBuf>, Error> {
+        match self.dump {
+            Some(ref dump) => Ok(glob_dump(dump, pattern)),
+            None => glob(pattern),
+        }
+    }
+    fn read(&self, path: &Path) -> Result<String, Error> {
+        match self.dump {
+            Some(ref dump) => dump.get(path).map(|x| x.to_string())
+                .ok_or_else(|| Error::Read(path.to_path_buf(),
+                    io::Error::new(io::ErrorKind::NotFound,
+                                   "file is not in the dump"))),
+            None => read_to_string(path)
+                .map_err(|e| Error::Read(path.to_path_buf(), e)),
+        }
+    }
+    fn file_id(&self, path: &Path) -> PathBuf {
+        match self.dump {
+            Some(_) => normalize(path),
+            None => file_id(path),
+        }
+    }
+}
+
+/// Lexically normalizes path, i.e. removes `.` and `..` where possible
+///
+/// Symlinks aren't resolved, so `a/../b` is always `b`, like in URLs.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match parts.last() {
+                Some(&Component::Normal(_)) => {
+                    parts.pop();
+                }
+                // `/..` is the same as `/`
+                Some(&Component::RootDir) | Some(&Component::Prefix(_)) => {}
+                _ => parts.push(component),
+            },
+            _ => parts.push(component),
+        }
+    }
+    parts.iter().collect()
+}
+
+fn prefix_matches(prefix: &Path, path: &Path) -> bool {
+    let mut components = path.components();
+    for pcomp in prefix.components() {
+        let comp = match components.next() {
+            Some(comp) => comp,
+            None => return false,
+        };
+        match (pcomp, comp) {
+            (Component::Normal(pname), Component::Normal(name)) => {
+                let pname = pname.to_string_lossy();
+                let name = name.to_string_lossy();
+                let ok = if is_glob(&name) {
+                    pname == name || pname == "*"
+                } else {
+                    glob_match(pname.as_bytes(), name.as_bytes())
+                };
+                if !ok {
+                    return false;
+                }
+            }
+            (pcomp, comp) => if pcomp != comp {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Resolves symlinks in the absolute `path` as if `root` was the
+/// filesystem root
+///
+/// Returns path inside the root, i.e. starting with `/`.
+fn canonicalize_in(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    let mut result = PathBuf::from("/");
+    let mut pending = path.components()
+        .map(|c| c.as_os_str().to_os_string())
+        .collect::<Vec<_>>();
+    pending.reverse();
+    let mut links = 0;
+    while let Some(name) = pending.pop() {
+        match Path::new(&name).components().next() {
+            Some(Component::Normal(_)) => {}
+            Some(Component::ParentDir) => {
+                result.pop();
+                continue;
+            }
+            _ => continue,
+        }
+        let candidate = result.join(&name);
+        let host = root.join(candidate.strip_prefix("/")
+            .expect("result is absolute"));
+        if !symlink_metadata(&host)?.file_type().is_symlink() {
+            result = candidate;
+            continue;
+        }
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(io::Error::other("too many levels of symlinks"));
+        }
+        let target = read_link(&host)?;
+        if target.is_absolute() {
+            result = PathBuf::from("/");
+        }
+        pending.extend(target.components().rev()
+            .map(|c| c.as_os_str().to_os_string()));
+    }
+    Ok(result)
+}
+
+fn file_id(path: &Path) -> PathBuf {
+    canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn is_glob(s: &str) -> bool {
+    s.contains(&['*', '?', '['][..])
+}
+
+/// Returns paths matching the pattern, sorted
+///
+/// Non-glob paths are returned as is even if they don't exist, so reading
+/// them reports an error. Glob which doesn't match anything returns an
+/// empty list, as in nginx.
+fn glob(pattern: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let name = match component {
+            Component::Normal(name) => name.to_string_lossy(),
+            _ => {
+                for path in &mut paths {
+                    path.push(component.as_os_str());
+                }
+                continue;
+            }
+        };
+        if !is_glob(&name) {
+            for path in &mut paths {
+                path.push(&*name);
+            }
+            continue;
+        }
+        let mut matched = Vec::new();
+        for dir in &paths {
+            let entries = match read_dir(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }) {
+                Ok(entries) => entries,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Read(dir.clone(), e)),
+            };
+            let mut names = Vec::new();
+            for entry in entries {
+                let entry = entry.map_err(|e| Error::Read(dir.clone(), e))?;
+                let entry_name = entry.file_name().to_string_lossy()
+                    .into_owned();
+                if entry_name.starts_with('.') && !name.starts_with('.') {
+                    continue;
+                }
+                if glob_match(name.as_bytes(), entry_name.as_bytes()) {
+                    names.push(entry_name);
+                }
+            }
+            names.sort();
+            matched.extend(names.into_iter().map(|n| dir.join(n)));
+        }
+        paths = matched;
+    }
+    Ok(paths)
+}
+
+/// Returns files of the dump matching the pattern, sorted
+///
+/// Like `glob`, non-glob paths are returned as is.
+fn glob_dump(dump: &Dump, pattern: &Path) -> Vec<PathBuf> {
+    let pattern = normalize(pattern);
+    if !is_glob(&pattern.to_string_lossy()) {
+        return vec![pattern];
+    }
+    let mut paths = dump.files().iter()
+        .map(|(path, _)| normalize(path))
+        .filter(|path| {
+            let mut names = path.components();
+            pattern.components().all(|pcomp| match (pcomp, names.next()) {
+                (Component::Normal(pname), Some(Component::Normal(name)))
+                => glob_match(pname.to_string_lossy().as_bytes(),
+                              name.to_string_lossy().as_bytes()),
+                (pcomp, comp) => Some(pcomp) == comp,
+            }) && names.next().is_none()
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// Matches name against shell pattern (`*`, `?`, `[...]`)
+///
+/// Only the last `*` is backtracked to, so it's linear for any pattern
+/// except the number of stars.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let mut pidx = 0;
+    let mut nidx = 0;
+    // position after the last star and name position it's matched up to
+    let mut star = None;
+    while nidx < name.len() {
+        let step = match pattern.get(pidx) {
+            Some(b'*') => {
+                star = Some((pidx + 1, nidx));
+                pidx += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match match_class(&pattern[pidx..], name[nidx]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                // unclosed class, bracket is a literal
+                None if name[nidx] == b'[' => Some(1),
+                None => None,
+            },
+            Some(&c) if c == name[nidx] => Some(1),
+            _ => None,
+        };
+        match (step, star) {
+            (Some(len), _) => {
+                pidx += len;
+                nidx += 1;
+            }
+            (None, Some((spidx, snidx))) => {
+                star = Some((spidx, snidx + 1));
+                pidx = spidx;
+                nidx = snidx + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[pidx..].iter().all(|&c| c == b'*')
+}
+
+/// Matches character against the `[...]` class at the start of pattern
+///
+/// Returns whether it matches and length of the class, or `None` if the
+/// class isn't closed.
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut idx = 1;
+    let negate = matches!(pattern.get(idx), Some(b'!') | Some(b'^'));
+    if negate {
+        idx += 1;
+    }
+    let mut found = false;
+    let mut first = true;
+    while idx < pattern.len() && (first || pattern[idx] != b']') {
+        first = false;
+        if pattern.get(idx+1) == Some(&b'-') &&
+           pattern.get(idx+2).is_some_and(|&e| e != b']')
+        {
+            found |= pattern[idx] <= c && c <= pattern[idx+2];
+            idx += 3;
+        } else {
+            found |= pattern[idx] == c;
+            idx += 1;
+        }
+    }
+    if idx >= pattern.len() {
+        return None;
+    }
+    Some((found != negate, idx + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use nginx_config::Pos;
+    use dump::Dump;
+    use {EntryPoint};
+    use super::{normalize, prefix_matches, glob_match, Error};
+    use super::Origin;
+
+    fn norm(path: &str) -> PathBuf {
+        normalize(Path::new(path))
+    }
+
+    fn prefix(prefix: &str, path: &str) -> bool {
+        prefix_matches(Path::new(prefix), Path::new(path))
+    }
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+
+    #[test]
+    fn normalize_path() {
+        assert_eq!(norm("a/./b"), PathBuf::from("a/b"));
+        assert_eq!(norm("a/../b"), PathBuf::from("b"));
+        assert_eq!(norm("a/../../b"), PathBuf::from("../b"));
+        assert_eq!(norm("/../etc"), PathBuf::from("/etc"));
+        assert_eq!(norm("/etc/nginx/../../x"), PathBuf::from("/x"));
+        assert_eq!(norm("./"), PathBuf::from(""));
+    }
+
+    #[test]
+    fn prefix_match() {
+        assert!(prefix("/etc/nginx", "/etc/nginx/a.conf"));
+        assert!(prefix("/etc/nginx", "/etc/nginx"));
+        assert!(!prefix("/etc/nginx", "/etc/nginx2/a.conf"));
+        assert!(!prefix("/etc/nginx/a", "/etc/nginx"));
+        assert!(!prefix("conf.d", "/conf.d/a.conf"));
+        assert!(prefix("sites/*", "sites/a/x.conf"));
+        assert!(prefix("sites/*.conf", "sites/a.conf"));
+        // glob in include must be allowed by the same pattern
+        assert!(prefix("sites/*", "sites/*.conf"));
+        assert!(prefix("sites/*.conf", "sites/*.conf"));
+        assert!(!prefix("sites/a*", "sites/*"));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("*", ""));
+        assert!(glob("*.conf", "a.conf"));
+        assert!(!glob("*.conf", "a.conf.bak"));
+        assert!(glob("a?c", "abc"));
+        assert!(!glob("a?c", "ac"));
+        assert!(glob("*a*b", "xaxab"));
+        assert!(!glob("*a*b", "xaxa"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(!glob("[!a-c]x", "bx"));
+        assert!(glob("[^a]x", "bx"));
+        assert!(glob("[]]", "]"));
+        assert!(glob("[ab", "[ab"));
+        assert!(!glob("[ab", "a"));
+        assert!(glob("a*", "a"));
+        assert!(!glob("", "a"));
+    }
+
+    #[test]
+    fn glob_many_stars() {
+        let pattern = "*a".repeat(30) + "b";
+        let name = "a".repeat(100);
+        assert!(!glob(&pattern, &name));
+        assert!(glob(&pattern, &(name + "b")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn canonicalize_in_root() {
+        use std::fs::{create_dir_all, remove_dir_all, write};
+        use std::os::unix::fs::symlink;
+        use std::process;
+        use super::AllowList;
+
+        let dir = ::std::env::temp_dir()
+            .join(format!("nginx-config-mod-test-{}", process::id()));
+        let root = dir.join("root");
+        create_dir_all(root.join("etc/nginx/conf.d")).unwrap();
+        create_dir_all(root.join("srv")).unwrap();
+        write(root.join("srv/site.conf"), "").unwrap();
+        write(root.join("etc/nginx/conf.d/ok.conf"), "").unwrap();
+        // absolute symlinks point inside the root
+        symlink("/srv/site.conf", root.join("etc/nginx/conf.d/bad.conf"))
+            .unwrap();
+        symlink("/etc/nginx/conf.d/ok.conf",
+                root.join("etc/nginx/conf.d/link.conf"))
+            .unwrap();
+        let mut list = AllowList::new(["/etc/nginx/conf.d"]);
+        list.canonicalize(true);
+        let check = |name: &str| list.check_in(
+            Path::new("/etc/nginx/conf.d/*.conf"),
+            &[root.join("etc/nginx/conf.d").join(name)],
+            &dir, Some(&root));
+        assert!(check("ok.conf").is_ok());
+        assert!(check("link.conf").is_ok());
+        match check("bad.conf") {
+            Err(Error::ResolvesOutside(_, real)) => {
+                assert_eq!(real, PathBuf::from("/srv/site.conf"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn origins() {
+        let dump = Dump::parse("\
+            # configuration file /etc/nginx/nginx.conf:\n\
+            http {\n\
+            \x20  include /etc/nginx/a.conf;\n\
+            \x20  include /etc/nginx/b.conf;\n\
+            }\n\
+            # configuration file /etc/nginx/a.conf:\n\
+            server {\n\
+            \x20   listen 80;\n\
+            }\n\
+            \n\
+            server_tokens off;\n\
+            # configuration file /etc/nginx/b.conf:\n\
+            gzip on;\n\
+        ").unwrap();
+        let mut cfg = dump.main_config(EntryPoint::Main).unwrap();
+        dump.expander().expand(&mut cfg).unwrap();
+        let names = cfg.all_directives()
+            .map(|dir| dir.item.directive_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["http", "server", "listen", "server_tokens",
+                           "gzip"]);
+        let origins = cfg.origins();
+        let file = |idx| match origins.get(idx) {
+            Origin::Included(path, _) => Some(path.display().to_string()),
+            Origin::Main => None,
+            Origin::Unknown => panic!("unknown origin of {}", idx),
+        };
+        assert_eq!(file(0), None);
+        assert_eq!(file(1), Some("/etc/nginx/a.conf".into()));
+        assert_eq!(file(3), Some("/etc/nginx/a.conf".into()));
+        assert_eq!(file(4), Some("/etc/nginx/b.conf".into()));
+        let pos = |line, column| Pos { line, column };
+        match origins.find(pos(5, 1)) {
+            Origin::Included(path, text) => {
+                assert_eq!(path, Path::new("/etc/nginx/a.conf"));
+                assert!(text.contains("server_tokens off;"));
+            }
+            origin => panic!("wrong origin {:?}", origin),
+        }
+        // all files have a directive at the start
+        assert_eq!(origins.find(pos(1, 1)), Origin::Unknown);
+        assert_eq!(origins.find(pos(9, 9)), Origin::Main);
+    }
+
+    #[test]
+    fn reexpand() {
+        let dump = Dump::parse("\
+            # configuration file /etc/nginx/nginx.conf:\n\
+            include /etc/nginx/a.conf;\n\
+            # configuration file /etc/nginx/a.conf:\n\
+            gzip on;\n\
+        ").unwrap();
+        let mut cfg = dump.main_config(EntryPoint::Http).unwrap();
+        dump.expander().expand(&mut cfg).unwrap();
+        dump.expander().expand(&mut cfg).unwrap();
+        match cfg.origins().get(0) {
+            Origin::Included(path, _) => {
+                assert_eq!(path, Path::new("/etc/nginx/a.conf"));
+            }
+            origin => panic!("wrong origin {:?}", origin),
+        }
+        // include at the start of the main file is replaced
+        assert_ne!(cfg.origins().find(Pos { line: 1, column: 1 }),
+                   Origin::Main);
+    }
+
+    #[test]
+    fn error_position() {
+        let dump = Dump::parse("\
+            # configuration file /etc/nginx/nginx.conf:\n\
+            include /etc/nginx/a.conf;\n\
+            # configuration file /etc/nginx/a.conf:\n\
+            gzip on;\n\
+            include /etc/nginx/missing.conf;\n\
+        ").unwrap();
+        let mut cfg = dump.main_config(EntryPoint::Http).unwrap();
+        match dump.expander().expand(&mut cfg) {
+            Err(Error::Include(Some(path), pos, err)) => {
+                assert_eq!(path, Path::new("/etc/nginx/a.conf"));
+                assert_eq!(pos, Pos { line: 2, column: 1 });
+                match *err {
+                    Error::Read(ref path, _) => assert_eq!(path,
+                        Path::new("/etc/nginx/missing.conf")),
+                    ref err => panic!("unexpected error {:?}", err),
+                }
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
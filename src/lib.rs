@@ -11,10 +11,23 @@
 extern crate nginx_config;
 extern crate url;
 #[macro_use] extern crate failure;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml_ng;
 
+mod args;
+mod canonical;
 mod config;
 mod errors;
+mod printer;
+mod recover;
+pub mod builder;
 pub mod checks;
+pub mod diagnostics;
+pub mod dump;
+pub mod export;
+pub mod includes;
 
-pub use errors::ReadError;
+pub use errors::{ReadError, ErrorKind, EntryPointError, CanonicalError};
 pub use config::{Config, EntryPoint};
+pub use recover::has_comments;
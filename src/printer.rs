@@ -0,0 +1,143 @@
+//! Writing config text from the AST
+//!
+//! `Display` of `nginx_config` never escapes values, so values having
+//! quotes are written in a way the parser can't read back. This module
+//! writes arguments taken from the AST, quoted as needed.
+use nginx_config::ast::{Directive, Item};
+
+use args;
+use builder;
+use export::is_quoted;
+
+
+/// A directive with arguments quoted as needed
+pub(crate) struct Node {
+    pub name: String,
+    pub args: Vec<String>,
+    pub children: Option<Vec<Node>>,
+}
+
+/// How the text is laid out
+pub(crate) struct Layout {
+    /// Number of spaces per nesting level
+    pub indent: usize,
+    /// Put an empty line before blocks, except the first one in a block
+    pub margins: bool,
+}
+
+/// Returns nodes of the directives, entries of `map` are children
+pub(crate) fn nodes(dirs: &[Directive]) -> Vec<Node> {
+    dirs.iter().map(node).collect()
+}
+
+fn node(dir: &Directive) -> Node {
+    let children = match dir.item {
+        Item::Map(ref map) => Some(args::map_entries(map).into_iter()
+            .map(|mut entry| Node {
+                name: normalize(&entry.remove(0)),
+                args: entry.iter().map(|arg| normalize(arg)).collect(),
+                children: None,
+            })
+            .collect()),
+        _ => dir.item.children().map(nodes),
+    };
+    Node {
+        name: dir.item.directive_name().to_string(),
+        args: args::args(&dir.item).iter()
+            .map(|arg| normalize(arg))
+            .collect(),
+        children,
+    }
+}
+
+/// Quotes the argument only if it's needed
+///
+/// Arguments the parser keeps as written (e.g. regular expressions) may
+/// be quoted, the quotes are dropped where they aren't needed, and kept
+/// if the string can't be written without them.
+fn normalize(arg: &str) -> String {
+    let bare = if is_quoted(arg) { &arg[1..arg.len()-1] } else { arg };
+    builder::quote(bare)
+        .or_else(|_| builder::quote(arg))
+        .unwrap_or_else(|_| arg.to_string())
+}
+
+/// Writes nodes at the nesting `level`
+pub(crate) fn write(buf: &mut String, nodes: &[Node], layout: &Layout,
+    level: usize)
+{
+    for node in nodes {
+        if layout.margins && node.children.is_some() && node.name != "if" &&
+            !buf.is_empty() && !buf.ends_with("{\n")
+        {
+            buf.push('\n');
+        }
+        indent(buf, layout, level);
+        buf.push_str(&node.name);
+        if node.name == "if" {
+            buf.push_str(" (");
+            buf.push_str(&node.args.join(" "));
+            buf.push(')');
+        } else {
+            for arg in &node.args {
+                buf.push(' ');
+                buf.push_str(arg);
+            }
+        }
+        match node.children {
+            Some(ref children) => {
+                buf.push_str(" {\n");
+                write(buf, children, layout, level + 1);
+                indent(buf, layout, level);
+                buf.push_str("}\n");
+            }
+            None => buf.push_str(";\n"),
+        }
+    }
+}
+
+fn indent(buf: &mut String, layout: &Layout, level: usize) {
+    for _ in 0..layout.indent * level {
+        buf.push(' ');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Config, EntryPoint};
+
+    fn parse(entry_point: EntryPoint, text: &str) -> Config {
+        Config::parse_str(entry_point, text, "a").unwrap()
+    }
+
+    #[test]
+    fn same_as_display() {
+        let main = parse(EntryPoint::Main, "daemon off; \
+            http { gzip on; map $a $b { default 0; x 1; } \
+            server { listen 80; if ($a) { return 403; } \
+            location / { root /a; limit_except GET { deny all; } } \
+            location /b {} } }");
+        assert_eq!(main.to_string_indented(4), main.to_string());
+        let server = parse(EntryPoint::Server,
+            "listen 80; root /a; location / { index i; }");
+        assert_eq!(server.to_string_indented(4), server.to_string());
+    }
+
+    #[test]
+    fn indent() {
+        let cfg = parse(EntryPoint::Server, "location / { if ($a) { \
+            return 403; } }");
+        assert_eq!(cfg.to_string_indented(2), "location / {\n  \
+            if ($a) {\n    return 403;\n  }\n}\n");
+    }
+
+    #[test]
+    fn inner_quotes() {
+        let text = "add_header X-A 'say \"hi\"';\nset $a \"it's\";\n";
+        let cfg = parse(EntryPoint::Http, text);
+        let copy = parse(EntryPoint::Http, &cfg.to_string_indented(4));
+        assert_eq!(copy.to_string_indented(4),
+                   "add_header X-A 'say \"hi\"';\n\nset $a \"it's\";\n");
+        assert!(copy.equivalent(&cfg));
+    }
+}
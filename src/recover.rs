@@ -0,0 +1,252 @@
+use std::path::PathBuf;
+
+use nginx_config;
+use nginx_config::ParseError;
+use nginx_config::ast::{Address, Directive, Item};
+
+use errors::syntax_error;
+
+
+/// Maximum number of errors reported for a single file
+const MAX_ERRORS: usize = 100;
+
+/// Parses text, skipping statements which have syntax errors
+///
+/// After an error the whole statement containing it (up to the next `;`
+/// or the end of its block) is replaced by spaces, keeping positions of
+/// everything else, and parsing is restarted. Returns `None` as the
+/// result if there is nothing left to parse.
+pub(crate) fn parse<T, F>(text: &str, parser: F)
+    -> (Option<T>, Vec<ParseError>)
+    where F: Fn(&str) -> Result<T, ParseError>
+{
+    let mut text = text.to_string();
+    let mut errors = Vec::new();
+    loop {
+        if text.trim().is_empty() {
+            return (None, errors);
+        }
+        let err = match parser(&text) {
+            Ok(result) => return (Some(result), errors),
+            Err(err) => err,
+        };
+        let skipped = syntax_error(&err)
+            .map(|(pos, _)| offset(&text, pos.line, pos.column))
+            .and_then(|offset| skip_statement(&mut text, offset));
+        errors.push(err);
+        if skipped.is_none() || errors.len() >= MAX_ERRORS {
+            return (None, errors);
+        }
+    }
+}
+
+/// Parses directives, working around bugs of the parser
+///
+/// The parser drops the first character of the path in
+/// `listen unix:/path`, so the path is taken from the source instead.
+pub(crate) fn parse_directives(text: &str)
+    -> Result<Vec<Directive>, ParseError>
+{
+    let mut directives = nginx_config::parse_directives(text)?;
+    fix_unix_listen(&mut directives, text);
+    Ok(directives)
+}
+
+fn fix_unix_listen(dirs: &mut [Directive], text: &str) {
+    for dir in dirs {
+        if let Item::Listen(ref mut lst) = dir.item {
+            if let Address::Unix(ref mut path) = lst.address {
+                let start = offset(text, dir.position.line,
+                                   dir.position.column);
+                if let Some(idx) = text[start..].find("unix:") {
+                    let rest = &text[start + idx + "unix:".len()..];
+                    let end = rest.find(|c: char| {
+                        c.is_whitespace() || c == ';' || c == '"' || c == '\''
+                    }).unwrap_or(rest.len());
+                    *path = PathBuf::from(&rest[..end]);
+                }
+            }
+        }
+        if let Some(children) = dir.item.children_mut() {
+            fix_unix_listen(children, text);
+        }
+    }
+}
+
+/// Converts one-based line and column into a byte offset
+fn offset(text: &str, line: usize, column: usize) -> usize {
+    let mut cur_line = 1;
+    let mut cur_column = 1;
+    for (idx, c) in text.char_indices() {
+        if cur_line == line && cur_column == column {
+            return idx;
+        }
+        if c == '\n' {
+            if cur_line == line {
+                return idx;
+            }
+            cur_line += 1;
+            cur_column = 1;
+        } else {
+            cur_column += 1;
+        }
+    }
+    text.len()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Semicolon,
+    Open,
+    Close,
+    Comment,
+    Other,
+}
+
+/// Returns true if the text has comments, which the parser drops
+pub fn has_comments(text: &str) -> bool {
+    lex(text).iter().any(|&(tok, _, _)| tok == Token::Comment)
+}
+
+/// Splits text into tokens significant for finding statement boundaries
+///
+/// Returns token and its byte range. Quoted strings are skipped as
+/// a whole, comments are skipped.
+fn tokens(text: &str) -> Vec<(Token, usize, usize)> {
+    let mut tokens = lex(text);
+    tokens.retain(|&(tok, _, _)| tok != Token::Comment);
+    tokens
+}
+
+fn lex(text: &str) -> Vec<(Token, usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let start = idx;
+        let token = match bytes[idx] {
+            b';' => Token::Semicolon,
+            b'{' => Token::Open,
+            b'}' => Token::Close,
+            // `#` starts a comment only at the start of a word
+            b'#' if idx == 0 || matches!(bytes[idx-1],
+                b';' | b'{' | b'}' | b' ' | b'\t' | b'\r' | b'\n') =>
+            {
+                while idx < bytes.len() && bytes[idx] != b'\n' {
+                    idx += 1;
+                }
+                result.push((Token::Comment, start, idx));
+                continue;
+            }
+            q @ b'"' | q @ b'\'' => {
+                idx += 1;
+                while idx < bytes.len() && bytes[idx] != q {
+                    if bytes[idx] == b'\\' {
+                        idx += 1;
+                    }
+                    idx += 1;
+                }
+                idx = (idx + 1).min(bytes.len());
+                result.push((Token::Other, start, idx));
+                continue;
+            }
+            b if (b as char).is_ascii_whitespace() => {
+                idx += 1;
+                continue;
+            }
+            _ => Token::Other,
+        };
+        idx += 1;
+        result.push((token, start, idx));
+    }
+    result
+}
+
+/// Finds the header of the innermost block which isn't closed
+///
+/// Removing just the header keeps directives inside the block.
+fn unclosed_block(tokens: &[(Token, usize, usize)])
+    -> Option<(usize, usize)>
+{
+    let mut stack = Vec::new();
+    let mut start = 0;
+    for (idx, &(tok, _, _)) in tokens.iter().enumerate() {
+        match tok {
+            Token::Open => {
+                stack.push((start, idx));
+                start = idx + 1;
+            }
+            Token::Close => {
+                stack.pop();
+                start = idx + 1;
+            }
+            Token::Semicolon => start = idx + 1,
+            Token::Comment | Token::Other => {}
+        }
+    }
+    stack.pop()
+}
+
+/// Blanks out the statement containing byte offset
+///
+/// Returns `None` if there was nothing to remove.
+fn skip_statement(text: &mut String, offset: usize) -> Option<()> {
+    let tokens = tokens(text);
+    // statement starts after the last boundary before the error
+    let first = tokens.iter()
+        .rposition(|&(tok, _, end)| end <= offset && tok != Token::Other)
+        .map(|idx| idx + 1)
+        .unwrap_or(0);
+    let mut depth = 0;
+    let mut last = None;
+    for (idx, &(tok, _, _)) in tokens.iter().enumerate().skip(first) {
+        match tok {
+            Token::Semicolon if depth == 0 => {
+                last = Some(idx);
+                break;
+            }
+            Token::Open => depth += 1,
+            Token::Close if depth == 0 => {
+                // unmatched closing brace is the error itself
+                if idx == first {
+                    last = Some(idx);
+                }
+                break;
+            }
+            Token::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    last = Some(idx);
+                    break;
+                }
+            }
+            _ => last = Some(idx),
+        }
+    }
+    let (first, last) = match last {
+        Some(last) if depth == 0 => (first, last),
+        _ => unclosed_block(&tokens)?,
+    };
+    let start = tokens[first].1;
+    let end = tokens[last].2;
+    let blank = text[start..end].chars()
+        .map(|c| if c == '\n' { '\n' } else { ' ' })
+        .collect::<String>();
+    text.replace_range(start..end, &blank);
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::has_comments;
+
+    #[test]
+    fn comments() {
+        assert!(has_comments("# top\nhttp {}"));
+        assert!(has_comments("gzip on; # trailing"));
+        assert!(has_comments("http {#inner\n}"));
+        assert!(!has_comments("return 301 https://x/#frag;"));
+        assert!(!has_comments("set $a \"# not a comment\";"));
+        assert!(!has_comments("gzip on;"));
+    }
+}